@@ -0,0 +1,176 @@
+use crate::error::{CelophaneError, Result};
+use ethers::types::U256;
+use std::fmt;
+
+/// A fixed-point token amount: an integer number of base units together with
+/// the number of decimals of the token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TokenAmount {
+    value: U256,
+    decimals: u8,
+}
+
+impl TokenAmount {
+    /// Largest number of decimals a `U256` can scale by: 10^78 overflows it.
+    pub const MAX_DECIMALS: u8 = 77;
+
+    pub fn new(value: U256, decimals: u8) -> Self {
+        TokenAmount { value, decimals }
+    }
+
+    /// Parses a decimal string such as `1.5` or `0.000001` into base units.
+    pub fn parse(amount: &str, decimals: u8) -> Result<Self> {
+        let invalid = || CelophaneError::InvalidAmount(amount.to_string());
+
+        let (integer, fraction) = match amount.find('.') {
+            Some(index) => (&amount[..index], &amount[index + 1..]),
+            None => (amount, ""),
+        };
+        let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+        if decimals > Self::MAX_DECIMALS
+            || (integer.is_empty() && fraction.is_empty())
+            || !is_digits(integer)
+            || !is_digits(fraction)
+            || fraction.len() > decimals as usize
+        {
+            return Err(invalid());
+        }
+
+        let parse_digits = |s: &str| {
+            if s.is_empty() {
+                Ok(U256::zero())
+            } else {
+                U256::from_dec_str(s).map_err(|_| invalid())
+            }
+        };
+        let integer = parse_digits(integer)?;
+        let fraction = parse_digits(fraction)?
+            .checked_mul(U256::exp10(decimals as usize - fraction.len()))
+            .ok_or_else(invalid)?;
+        let value = integer
+            .checked_mul(U256::exp10(decimals as usize))
+            .and_then(|value| value.checked_add(fraction))
+            .ok_or_else(invalid)?;
+
+        Ok(TokenAmount { value, decimals })
+    }
+
+    /// The amount in base units.
+    pub fn value(&self) -> U256 {
+        self.value
+    }
+
+    pub fn decimals(&self) -> u8 {
+        self.decimals
+    }
+}
+
+impl fmt::Display for TokenAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Amounts with more decimals than can be scaled by are left in base
+        // units.
+        if self.decimals > Self::MAX_DECIMALS {
+            return write!(f, "{}", self.value);
+        }
+        let (integer, fraction) = self.value.div_mod(U256::exp10(self.decimals as usize));
+        if fraction.is_zero() {
+            return write!(f, "{}", integer);
+        }
+        let fraction = format!(
+            "{:0>width$}",
+            fraction.to_string(),
+            width = self.decimals as usize
+        );
+        write!(f, "{}.{}", integer, fraction.trim_end_matches('0'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(amount: &str, decimals: u8) -> Option<U256> {
+        TokenAmount::parse(amount, decimals)
+            .ok()
+            .map(|amount| amount.value())
+    }
+
+    #[test]
+    fn parses_decimal_amounts() {
+        let one = U256::exp10(18);
+        assert_eq!(parse("1", 18), Some(one));
+        assert_eq!(parse("1.5", 18), Some(one * 3 / 2));
+        assert_eq!(parse("0.000001", 18), Some(U256::exp10(12)));
+        assert_eq!(parse("0.000001", 6), Some(U256::one()));
+        assert_eq!(parse("42", 0), Some(U256::from(42)));
+    }
+
+    #[test]
+    fn parses_leading_and_trailing_zeros() {
+        assert_eq!(parse("007.50", 2), Some(U256::from(750)));
+        assert_eq!(parse("0.10", 2), Some(U256::from(10)));
+        assert_eq!(parse(".5", 1), Some(U256::from(5)));
+        assert_eq!(parse("5.", 1), Some(U256::from(50)));
+        assert_eq!(parse("0", 18), Some(U256::zero()));
+    }
+
+    #[test]
+    fn rejects_more_fraction_digits_than_decimals() {
+        // Amounts are never rounded, even when the extra digits are zeros.
+        assert_eq!(parse("0.0000001", 6), None);
+        assert_eq!(parse("1.000", 2), None);
+        assert_eq!(parse("1.5", 0), None);
+    }
+
+    #[test]
+    fn rejects_malformed_amounts() {
+        for amount in &["", ".", "-1", "+1", "1.2.3", "1e18", " 1", "1,5", "0x10"] {
+            assert_eq!(parse(amount, 18), None, "{:?}", amount);
+        }
+    }
+
+    #[test]
+    fn rejects_overflowing_amounts() {
+        let max = U256::MAX.to_string();
+        assert_eq!(parse(&max, 0), Some(U256::MAX));
+        assert_eq!(parse(&max, 1), None);
+        assert_eq!(parse(&format!("{}0", max), 0), None);
+    }
+
+    #[test]
+    fn rejects_unsupported_decimals() {
+        assert_eq!(parse("1", 77), Some(U256::exp10(77)));
+        assert_eq!(parse("0", 78), None);
+        assert_eq!(parse("1", 80), None);
+        assert_eq!(parse("1", u8::MAX), None);
+    }
+
+    #[test]
+    fn formats_without_trailing_zeros() {
+        let format =
+            |value: u64, decimals| TokenAmount::new(U256::from(value), decimals).to_string();
+        assert_eq!(format(1_500_000, 6), "1.5");
+        assert_eq!(format(1, 6), "0.000001");
+        assert_eq!(format(2_000_000, 6), "2");
+        assert_eq!(format(0, 18), "0");
+        assert_eq!(format(1_234, 0), "1234");
+        assert_eq!(format(1_001, 3), "1.001");
+    }
+
+    #[test]
+    fn formats_unsupported_decimals_in_base_units() {
+        assert_eq!(TokenAmount::new(U256::from(15), 80).to_string(), "15");
+        assert_eq!(
+            TokenAmount::new(U256::MAX, u8::MAX).to_string(),
+            U256::MAX.to_string()
+        );
+        assert_eq!(TokenAmount::new(U256::exp10(76) * 5, 77).to_string(), "0.5");
+    }
+
+    #[test]
+    fn formats_what_it_parses() {
+        for amount in &["0", "1", "0.000000000000000001", "123.456", "1000000"] {
+            assert_eq!(TokenAmount::parse(amount, 18).unwrap().to_string(), *amount);
+        }
+    }
+}
@@ -1,21 +1,189 @@
-use anyhow::{anyhow, Result};
+use crate::error::{CelophaneError, Result};
 use ethers::prelude::abigen;
 use ethers::providers::Middleware;
-use ethers::types::Address;
+use ethers::types::{Address, BlockNumber, Log, H256, U256, U64};
+use ethers::utils::keccak256;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
 use std::sync::Arc;
+use tokio::try_join;
 
 const REGISTRY_ADDRESS: &str = "000000000000000000000000000000000000ce10";
 
-const EXCHANGE: &str = "Exchange";
-const GOLD_TOKEN: &str = "GoldToken";
-const STABLE_TOKEN: &str = "StableToken";
-const STABLE_TOKEN_EUR: &str = "StableTokenEUR";
+pub const EXCHANGE: &str = "Exchange";
+pub const EXCHANGE_EUR: &str = "ExchangeEUR";
+pub const GAS_PRICE_MINIMUM: &str = "GasPriceMinimum";
+pub const GOLD_TOKEN: &str = "GoldToken";
+pub const STABLE_TOKEN: &str = "StableToken";
+pub const STABLE_TOKEN_EUR: &str = "StableTokenEUR";
+
+/// Decimals of CELO and the Mento stable tokens.
+pub const TOKEN_DECIMALS: u8 = 18;
+
+/// A token whose contract is registered in the registry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Token {
+    Celo,
+    Cusd,
+    Ceur,
+}
+
+impl Token {
+    pub const ALL: [Token; 3] = [Token::Celo, Token::Cusd, Token::Ceur];
+    pub const SYMBOLS: &'static [&'static str] = &["CELO", "cUSD", "cEUR"];
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Token::Celo => "CELO",
+            Token::Cusd => "cUSD",
+            Token::Ceur => "cEUR",
+        }
+    }
+
+    pub fn registry_id(&self) -> &'static str {
+        match self {
+            Token::Celo => GOLD_TOKEN,
+            Token::Cusd => STABLE_TOKEN,
+            Token::Ceur => STABLE_TOKEN_EUR,
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+impl FromStr for Token {
+    type Err = CelophaneError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Token::ALL
+            .iter()
+            .find(|token| token.symbol().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| CelophaneError::UnknownToken(s.to_string()))
+    }
+}
+
+/// An ERC-20 token given by contract address or registry identifier.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenSpec {
+    Address(Address),
+    Registry(String),
+}
+
+impl TokenSpec {
+    /// The registry token this is, if any.
+    pub fn token(&self) -> Option<Token> {
+        match self {
+            TokenSpec::Registry(name) => Token::ALL
+                .iter()
+                .find(|token| token.registry_id() == name)
+                .copied(),
+            TokenSpec::Address(_) => None,
+        }
+    }
+}
+
+impl From<Token> for TokenSpec {
+    fn from(token: Token) -> Self {
+        TokenSpec::Registry(token.registry_id().to_string())
+    }
+}
+
+impl fmt::Display for TokenSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenSpec::Address(address) => write!(f, "{:?}", address),
+            TokenSpec::Registry(name) => f.write_str(name),
+        }
+    }
+}
+
+impl FromStr for TokenSpec {
+    type Err = CelophaneError;
+
+    /// Parses a contract address, a registry token symbol such as "cUSD", or
+    /// any other registry identifier.
+    fn from_str(s: &str) -> Result<Self> {
+        if s.starts_with("0x") {
+            return s
+                .parse()
+                .map(TokenSpec::Address)
+                .map_err(|_| CelophaneError::UnknownToken(s.to_string()));
+        }
+        if s.is_empty() {
+            return Err(CelophaneError::UnknownToken(s.to_string()));
+        }
+        match s.parse::<Token>() {
+            Ok(token) => Ok(token.into()),
+            Err(_) => Ok(TokenSpec::Registry(s.to_string())),
+        }
+    }
+}
+
+/// Descriptive fields of an ERC-20 token.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TokenMetadata {
+    pub address: Address,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+impl TokenMetadata {
+    /// Reads the metadata of `token` in parallel.
+    pub async fn fetch<M: Middleware>(token: &Erc20<M>) -> Result<Self> {
+        let (name, symbol, decimals) = (token.name(), token.symbol(), token.decimals());
+        let (name, symbol, decimals) = try_join!(name.call(), symbol.call(), decimals.call())?;
+        Ok(TokenMetadata {
+            address: token.address(),
+            name,
+            symbol,
+            decimals,
+        })
+    }
+}
 
 abigen!(Erc20, "./src/abis/IERC20.json");
 abigen!(Registry, "./src/abis/Registry.json");
 abigen!(Exchange, "./src/abis/Exchange.json");
+abigen!(GasPriceMinimum, "./src/abis/GasPriceMinimum.json");
+abigen!(Multicall, "./src/abis/Multicall.json");
 
-async fn get_erc20_token<M: Middleware>(client: Arc<M>, name: &str) -> Result<Erc20<M>> {
+/// A Mento exchange pool, trading CELO against a stable token.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExchangePool {
+    /// Symbol of the stable token, e.g. "cUSD".
+    pub stable: String,
+    /// Registry identifier of the stable token contract.
+    pub stable_token: String,
+    /// Registry identifier of the exchange contract.
+    pub exchange: String,
+}
+
+impl ExchangePool {
+    pub fn new(stable: &str, stable_token: &str, exchange: &str) -> Self {
+        ExchangePool {
+            stable: stable.to_string(),
+            stable_token: stable_token.to_string(),
+            exchange: exchange.to_string(),
+        }
+    }
+
+    /// The pools of the stable tokens known to this crate: cUSD and cEUR.
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            ExchangePool::new(Token::Cusd.symbol(), STABLE_TOKEN, EXCHANGE),
+            ExchangePool::new(Token::Ceur.symbol(), STABLE_TOKEN_EUR, EXCHANGE_EUR),
+        ]
+    }
+}
+
+pub async fn get_erc20_token<M: Middleware>(client: Arc<M>, name: &str) -> Result<Erc20<M>> {
     let address = registry_lookup(client.clone(), name).await?;
     let token = Erc20::new(address, client.clone());
     Ok(token)
@@ -33,21 +201,252 @@ pub async fn get_ceur_token<M: Middleware>(client: Arc<M>) -> Result<Erc20<M>> {
     get_erc20_token(client, STABLE_TOKEN_EUR).await
 }
 
-pub async fn get_exchange<M: Middleware>(client: Arc<M>) -> Result<Exchange<M>> {
-    let exchange_address = registry_lookup(client.clone(), EXCHANGE).await?;
+pub async fn get_exchange_contract<M: Middleware>(
+    client: Arc<M>,
+    name: &str,
+) -> Result<Exchange<M>> {
+    let exchange_address = registry_lookup(client.clone(), name).await?;
     let exchange = Exchange::new(exchange_address, client.clone());
     Ok(exchange)
 }
 
+/// The CELO/cUSD exchange.
+pub async fn get_exchange<M: Middleware>(client: Arc<M>) -> Result<Exchange<M>> {
+    get_exchange_contract(client, EXCHANGE).await
+}
+
+/// The CELO/cEUR exchange.
+pub async fn get_exchange_eur<M: Middleware>(client: Arc<M>) -> Result<Exchange<M>> {
+    get_exchange_contract(client, EXCHANGE_EUR).await
+}
+
 pub async fn registry_lookup<M: Middleware>(client: Arc<M>, name: &str) -> Result<Address> {
-    let registry_address: Address = REGISTRY_ADDRESS.parse()?;
-    let registry = Registry::new(registry_address, client);
-    match registry
-        .get_address_for_string(name.to_string())
-        .call()
-        .await
-    {
-        Ok(address) => Ok(address),
-        Err(_) => Err(anyhow!("registry lookup failed (name: {})", name)),
+    registry_lookup_at(client, name, None).await
+}
+
+/// Like [`registry_lookup`], but against the registry state at `block`.
+pub async fn registry_lookup_at<M: Middleware>(
+    client: Arc<M>,
+    name: &str,
+    block: Option<BlockNumber>,
+) -> Result<Address> {
+    let registry = get_registry(client, registry_address());
+    registry_entry(&registry, name, block).await
+}
+
+/// Address of `name` in `registry` at `block`, for registries deployed
+/// elsewhere than at [`registry_address`].
+pub async fn registry_entry<M: Middleware>(
+    registry: &Registry<M>,
+    name: &str,
+    block: Option<BlockNumber>,
+) -> Result<Address> {
+    let mut call = registry.get_address_for_string(name.to_string());
+    if let Some(block) = block {
+        call = call.block(block);
+    }
+    registry_result(name, call.call().await?)
+}
+
+/// `address`, as looked up in the registry for `name`, or an error if there is
+/// no such entry. The registry maps unknown identifiers to the zero address
+/// rather than reverting.
+pub(crate) fn registry_result(name: &str, address: Address) -> Result<Address> {
+    if address == Address::zero() {
+        return Err(CelophaneError::RegistryEntryMissing {
+            name: name.to_string(),
+        });
+    }
+    Ok(address)
+}
+
+/// A registry entry being pointed to a new address.
+#[derive(Clone, Debug)]
+pub struct RegistryUpdate {
+    pub identifier: String,
+    pub address: Address,
+    pub block_number: U64,
+    pub transaction_hash: H256,
+}
+
+/// Replays the `RegistryUpdated` events of `registry` between `from_block`
+/// and `to_block` (inclusive), optionally restricted to a single identifier,
+/// querying at most `page_size` blocks at a time.
+pub async fn registry_history<M: Middleware>(
+    registry: &Registry<M>,
+    name: Option<&str>,
+    from_block: u64,
+    to_block: u64,
+    page_size: u64,
+) -> Result<Vec<RegistryUpdate>> {
+    let mut updates = Vec::new();
+    for (page_start, page_end) in block_pages(from_block, to_block, page_size) {
+        let mut event = registry
+            .registry_updated_filter()
+            .from_block(page_start)
+            .to_block(page_end);
+        if let Some(name) = name {
+            event = event.topic1(H256::from(keccak256(name)));
+        }
+        let page = event.query_with_meta().await?;
+        updates.extend(page.into_iter().map(|(update, meta)| RegistryUpdate {
+            identifier: update.identifier,
+            address: update.addr,
+            block_number: meta.block_number,
+            transaction_hash: meta.transaction_hash,
+        }));
     }
+    Ok(updates)
+}
+
+/// A token transfer to or from an account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TokenTransfer {
+    pub from: Address,
+    pub to: Address,
+    pub value: U256,
+    pub block_number: U64,
+    /// Position of the `Transfer` log in its block.
+    pub log_index: U256,
+    pub transaction_hash: H256,
+}
+
+/// Reads the `Transfer` events of `token` sent or received by `account`
+/// between `from_block` and `to_block` (inclusive), querying at most
+/// `page_size` blocks at a time. Transfers are sorted by block and log index,
+/// i.e. in the order they were applied.
+pub async fn transfer_history<M: Middleware>(
+    token: &Erc20<M>,
+    account: Address,
+    from_block: u64,
+    to_block: u64,
+    page_size: u64,
+) -> Result<Vec<TokenTransfer>> {
+    let account_topic = H256::from(account);
+    let client = token.client();
+    let mut transfers = Vec::new();
+    for (page_start, page_end) in block_pages(from_block, to_block, page_size) {
+        // The logs are read directly since the event metadata of
+        // `query_with_meta` does not include the log index.
+        let sent = token
+            .transfer_filter()
+            .from_block(page_start)
+            .to_block(page_end)
+            .topic1(account_topic)
+            .filter;
+        let received = token
+            .transfer_filter()
+            .from_block(page_start)
+            .to_block(page_end)
+            .topic2(account_topic)
+            .filter;
+        let (sent, received) = try_join!(client.get_logs(&sent), client.get_logs(&received))
+            .map_err(CelophaneError::from_node)?;
+
+        let mut page = Vec::with_capacity(sent.len() + received.len());
+        for log in sent {
+            page.push(decode_transfer(token, log)?);
+        }
+        for log in received {
+            let transfer = decode_transfer(token, log)?;
+            // Transfers to oneself match both filters.
+            if transfer.from != account {
+                page.push(transfer);
+            }
+        }
+        page.sort_by_key(|transfer| (transfer.block_number, transfer.log_index));
+        transfers.extend(page);
+    }
+    Ok(transfers)
+}
+
+fn decode_transfer<M: Middleware>(token: &Erc20<M>, log: Log) -> Result<TokenTransfer> {
+    let (block_number, log_index, transaction_hash) =
+        match (log.block_number, log.log_index, log.transaction_hash) {
+            (Some(block_number), Some(log_index), Some(transaction_hash)) => {
+                (block_number, log_index, transaction_hash)
+            }
+            _ => {
+                return Err(CelophaneError::Decode(
+                    "Transfer log without a block, log index or transaction".to_string(),
+                ))
+            }
+        };
+    let event: TransferFilter = token
+        .decode_event("Transfer", log.topics, log.data)
+        .map_err(|err| CelophaneError::Decode(err.to_string()))?;
+    Ok(TokenTransfer {
+        from: event.from,
+        to: event.to,
+        value: event.value,
+        block_number,
+        log_index,
+        transaction_hash,
+    })
+}
+
+/// An `Approval` of a spender by a token owner.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TokenApproval {
+    pub owner: Address,
+    pub spender: Address,
+    pub value: U256,
+    pub block_number: U64,
+    pub transaction_hash: H256,
+}
+
+/// Reads the `Approval` events of `token` emitted for `owner` between
+/// `from_block` and `to_block` (inclusive), querying at most `page_size`
+/// blocks at a time. Approvals are sorted by block.
+pub async fn approval_history<M: Middleware>(
+    token: &Erc20<M>,
+    owner: Address,
+    from_block: u64,
+    to_block: u64,
+    page_size: u64,
+) -> Result<Vec<TokenApproval>> {
+    let mut approvals = Vec::new();
+    for (page_start, page_end) in block_pages(from_block, to_block, page_size) {
+        let page = token
+            .approval_filter()
+            .from_block(page_start)
+            .to_block(page_end)
+            .topic1(H256::from(owner))
+            .query_with_meta()
+            .await?;
+        approvals.extend(page.into_iter().map(|(event, meta)| TokenApproval {
+            owner: event.owner,
+            spender: event.spender,
+            value: event.value,
+            block_number: meta.block_number,
+            transaction_hash: meta.transaction_hash,
+        }));
+    }
+    Ok(approvals)
+}
+
+/// Splits `from_block..=to_block` into ranges of at most `page_size` blocks,
+/// to keep log queries within the node's limits.
+pub(crate) fn block_pages(from_block: u64, to_block: u64, page_size: u64) -> Vec<(u64, u64)> {
+    let page_size = page_size.max(1);
+    let mut pages = Vec::new();
+    let mut page_start = from_block;
+    while page_start <= to_block {
+        let page_end = to_block.min(page_start.saturating_add(page_size - 1));
+        pages.push((page_start, page_end));
+        page_start = match page_end.checked_add(1) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    pages
+}
+
+/// Address the registry is deployed at on the public Celo networks.
+pub fn registry_address() -> Address {
+    REGISTRY_ADDRESS.parse().unwrap()
+}
+
+pub(crate) fn get_registry<M: Middleware>(client: Arc<M>, address: Address) -> Registry<M> {
+    Registry::new(address, client)
 }
@@ -0,0 +1,397 @@
+use crate::celo::{
+    self, Erc20, Exchange, ExchangePool, GasPriceMinimum, Registry, Token, TokenSpec,
+};
+use crate::error::{CelophaneError, Result};
+use crate::fee_currency::FeeCurrency;
+use crate::multicall::CallBatch;
+use crate::registry::RegistryCache;
+use crate::transport::BatchClient;
+use ethers::providers::{Middleware, PendingTransaction};
+use ethers::types::{Address, BlockNumber, TransactionRequest, U256, U64};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Expected cost of a transaction, in the currency its fees are paid in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeeEstimate {
+    pub gas: U256,
+    pub gas_price: U256,
+    /// Gas fee plus gateway fee.
+    pub fee: U256,
+}
+
+/// Entry point to the Celo core contracts, resolved through the on-chain registry.
+#[derive(Debug)]
+pub struct CeloClient<M> {
+    client: Arc<M>,
+    registry_address: Address,
+    registry: Option<Arc<Mutex<RegistryCache>>>,
+    registry_block: Option<BlockNumber>,
+    multicall: Option<Address>,
+    batch_client: Option<Arc<dyn BatchClient>>,
+    /// Fetched on first use and shared between clones.
+    chain_id: Arc<Mutex<Option<u64>>>,
+}
+
+impl<M> Clone for CeloClient<M> {
+    fn clone(&self) -> Self {
+        CeloClient {
+            client: self.client.clone(),
+            registry_address: self.registry_address,
+            registry: self.registry.clone(),
+            registry_block: self.registry_block,
+            multicall: self.multicall,
+            batch_client: self.batch_client.clone(),
+            chain_id: self.chain_id.clone(),
+        }
+    }
+}
+
+impl<M: Middleware> CeloClient<M> {
+    pub fn new(client: Arc<M>) -> Self {
+        CeloClient {
+            client,
+            registry_address: celo::registry_address(),
+            registry: None,
+            registry_block: None,
+            multicall: None,
+            batch_client: None,
+            chain_id: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Uses the registry deployed at `address`, e.g. on a development chain.
+    pub fn with_registry_address(mut self, address: Address) -> Self {
+        self.registry_address = address;
+        self
+    }
+
+    /// Resolves registry entries through `cache`, pinned to `block` if given.
+    pub fn with_registry_cache(mut self, cache: RegistryCache, block: Option<U64>) -> Self {
+        self.registry = Some(Arc::new(Mutex::new(cache)));
+        self.registry_block = block.map(BlockNumber::Number);
+        self
+    }
+
+    /// Resolves registry entries at `block` rather than at the latest block.
+    pub fn with_registry_block(mut self, block: BlockNumber) -> Self {
+        self.registry_block = Some(block);
+        self
+    }
+
+    /// Aggregates batched reads through the Multicall contract at `address`.
+    pub fn with_multicall(mut self, address: Address) -> Self {
+        self.multicall = Some(address);
+        self
+    }
+
+    /// Sends batched reads that Multicall cannot aggregate as JSON-RPC
+    /// batches through `batch_client`, which must reach the same node as the
+    /// middleware.
+    pub fn with_batch_client(mut self, batch_client: Arc<dyn BatchClient>) -> Self {
+        self.batch_client = Some(batch_client);
+        self
+    }
+
+    /// A batch of read-only calls, aggregated through Multicall if configured.
+    pub fn batch(&self) -> CallBatch<M> {
+        CallBatch::new(self.client.clone(), self.multicall).batch_client(self.batch_client.clone())
+    }
+
+    /// A snapshot of the registry cache, e.g. to persist it.
+    pub async fn registry_cache(&self) -> Option<RegistryCache> {
+        match &self.registry {
+            Some(registry) => Some(registry.lock().await.clone()),
+            None => None,
+        }
+    }
+
+    /// The underlying middleware.
+    pub fn client(&self) -> Arc<M> {
+        self.client.clone()
+    }
+
+    /// Id of the chain the client is connected to.
+    pub async fn chain_id(&self) -> Result<u64> {
+        let mut chain_id = self.chain_id.lock().await;
+        match *chain_id {
+            Some(chain_id) => Ok(chain_id),
+            None => {
+                let id = self
+                    .client
+                    .get_chainid()
+                    .await
+                    .map_err(CelophaneError::from_node)?
+                    .as_u64();
+                *chain_id = Some(id);
+                Ok(id)
+            }
+        }
+    }
+
+    /// The registry contract.
+    pub fn registry(&self) -> Registry<M> {
+        Registry::new(self.registry_address, self.client.clone())
+    }
+
+    pub async fn celo_token(&self) -> Result<Erc20<M>> {
+        self.erc20_token(celo::GOLD_TOKEN).await
+    }
+
+    pub async fn cusd_token(&self) -> Result<Erc20<M>> {
+        self.erc20_token(celo::STABLE_TOKEN).await
+    }
+
+    pub async fn ceur_token(&self) -> Result<Erc20<M>> {
+        self.erc20_token(celo::STABLE_TOKEN_EUR).await
+    }
+
+    pub async fn token(&self, token: Token) -> Result<Erc20<M>> {
+        self.erc20_token(token.registry_id()).await
+    }
+
+    /// The ERC-20 token `spec` refers to.
+    pub async fn erc20(&self, spec: &TokenSpec) -> Result<Erc20<M>> {
+        match spec {
+            TokenSpec::Address(address) => Ok(Erc20::new(*address, self.client.clone())),
+            TokenSpec::Registry(name) => self.erc20_token(name).await,
+        }
+    }
+
+    /// The CELO/cUSD exchange.
+    pub async fn exchange(&self) -> Result<Exchange<M>> {
+        self.exchange_contract(celo::EXCHANGE).await
+    }
+
+    /// The exchange of `pool`.
+    pub async fn pool_exchange(&self, pool: &ExchangePool) -> Result<Exchange<M>> {
+        self.exchange_contract(&pool.exchange).await
+    }
+
+    /// The stable token traded in `pool`.
+    pub async fn pool_stable_token(&self, pool: &ExchangePool) -> Result<Erc20<M>> {
+        self.erc20_token(&pool.stable_token).await
+    }
+
+    /// The registry cache and the block its entries for the registry block
+    /// are keyed by, unless they cannot be cached: entries at the pending
+    /// block change with every block.
+    fn cached_registry(&self) -> Option<(&Mutex<RegistryCache>, Option<U64>)> {
+        let block = match self.registry_block {
+            None | Some(BlockNumber::Latest) => None,
+            Some(BlockNumber::Earliest) => Some(U64::zero()),
+            Some(BlockNumber::Number(block)) => Some(block),
+            Some(BlockNumber::Pending) => return None,
+        };
+        self.registry.as_deref().map(|cache| (cache, block))
+    }
+
+    pub async fn registry_lookup(&self, name: &str) -> Result<Address> {
+        if let Some((registry, block)) = self.cached_registry() {
+            let chain_id = self.chain_id().await?;
+            let mut cache = registry.lock().await;
+            cache
+                .resolve_all(
+                    self.client.clone(),
+                    self.batch(),
+                    chain_id,
+                    self.registry_address,
+                    block,
+                )
+                .await?;
+            if let Some(address) = cache.get(chain_id, self.registry_address, block, name) {
+                return Ok(address);
+            }
+        }
+        // Not a core contract (or no cache): ask the registry directly.
+        celo::registry_entry(&self.registry(), name, self.registry_block).await
+    }
+
+    /// Looks up `names` in the registry at once: through the cache if there is
+    /// one, and in a single batch of calls for the names it lacks.
+    pub async fn registry_lookup_all(&self, names: &[&str]) -> Result<Vec<Result<Address>>> {
+        let cached: Vec<Option<Address>> = match self.cached_registry() {
+            Some((registry, block)) => {
+                let chain_id = self.chain_id().await?;
+                let mut cache = registry.lock().await;
+                cache
+                    .resolve_all(
+                        self.client.clone(),
+                        self.batch(),
+                        chain_id,
+                        self.registry_address,
+                        block,
+                    )
+                    .await?;
+                names
+                    .iter()
+                    .map(|name| cache.get(chain_id, self.registry_address, block, name))
+                    .collect()
+            }
+            None => vec![None; names.len()],
+        };
+
+        let registry = self.registry();
+        let calls = names
+            .iter()
+            .zip(&cached)
+            .filter(|(_, address)| address.is_none())
+            .map(|(name, _)| registry.get_address_for_string(name.to_string()))
+            .collect();
+        let mut lookups = self
+            .batch()
+            .block(self.registry_block)
+            .call(calls)
+            .await
+            .into_iter();
+
+        Ok(names
+            .iter()
+            .zip(cached)
+            .map(|(name, address)| match address {
+                Some(address) => Ok(address),
+                None => lookups
+                    .next()
+                    .expect("one lookup per uncached name")
+                    .and_then(|address| celo::registry_result(name, address)),
+            })
+            .collect())
+    }
+
+    /// The last block mined at or before `timestamp`, found by binary search
+    /// over the block timestamps.
+    pub async fn block_at_timestamp(&self, timestamp: U256) -> Result<U64> {
+        let latest = self
+            .client
+            .get_block_number()
+            .await
+            .map_err(CelophaneError::from_node)?;
+        if self.block_timestamp(latest).await? <= timestamp {
+            return Ok(latest);
+        }
+        if self.block_timestamp(U64::zero()).await? > timestamp {
+            return Err(CelophaneError::BlockNotFound(format!(
+                "no block at or before timestamp {}",
+                timestamp
+            )));
+        }
+
+        // Invariant: `low` is at or before `timestamp`, `high` after it.
+        let (mut low, mut high) = (U64::zero(), latest);
+        while high - low > U64::one() {
+            let middle = low + (high - low) / U64::from(2);
+            if self.block_timestamp(middle).await? <= timestamp {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        Ok(low)
+    }
+
+    async fn block_timestamp(&self, block: U64) -> Result<U256> {
+        let block = self
+            .client
+            .get_block(BlockNumber::Number(block))
+            .await
+            .map_err(CelophaneError::from_node)?
+            .ok_or_else(|| CelophaneError::BlockNotFound(format!("block {} not found", block)))?;
+        Ok(block.timestamp)
+    }
+
+    /// Builds a transaction sending `amount` of `token` to `to`. CELO is sent
+    /// natively, other tokens through their ERC-20 `transfer`.
+    pub async fn transfer_request(
+        &self,
+        token: &TokenSpec,
+        to: Address,
+        amount: U256,
+    ) -> Result<TransactionRequest> {
+        match token.token() {
+            Some(Token::Celo) => Ok(TransactionRequest::pay(to, amount)),
+            _ => Ok(self.erc20(token).await?.transfer(to, amount).tx),
+        }
+    }
+
+    /// Sends `amount` of `token` to `to`. Requires a signing middleware.
+    pub async fn transfer(
+        &self,
+        token: &TokenSpec,
+        to: Address,
+        amount: U256,
+    ) -> Result<PendingTransaction<'_, M::Provider>> {
+        let tx = self.transfer_request(token, to, amount).await?;
+        self.send_transaction(tx).await
+    }
+
+    pub async fn send_transaction(
+        &self,
+        tx: TransactionRequest,
+    ) -> Result<PendingTransaction<'_, M::Provider>> {
+        self.client
+            .send_transaction(tx, None)
+            .await
+            .map_err(CelophaneError::from_node)
+    }
+
+    /// Estimates the gas and fee of `tx` with the middleware's gas price.
+    pub async fn estimate_fee(&self, tx: &TransactionRequest) -> Result<FeeEstimate> {
+        let gas = match tx.gas {
+            Some(gas) => gas,
+            None => self
+                .client
+                .estimate_gas(tx)
+                .await
+                .map_err(CelophaneError::from_node)?,
+        };
+        let gas_price = match tx.gas_price {
+            Some(gas_price) => gas_price,
+            None => self
+                .client
+                .get_gas_price()
+                .await
+                .map_err(CelophaneError::from_node)?,
+        };
+        let fee = gas * gas_price + tx.gateway_fee.unwrap_or_default();
+        Ok(FeeEstimate {
+            gas,
+            gas_price,
+            fee,
+        })
+    }
+
+    /// Fee currency paying fees in `token`, `None` for CELO. The gas price is
+    /// twice the current minimum, so that the transaction stays valid if the
+    /// minimum rises before it is mined.
+    pub async fn fee_currency(&self, token: Token) -> Result<Option<FeeCurrency>> {
+        if token == Token::Celo {
+            return Ok(None);
+        }
+        let address = self.registry_lookup(token.registry_id()).await?;
+        let gas_price_minimum = self.registry_lookup(celo::GAS_PRICE_MINIMUM).await?;
+        let gas_price_minimum = GasPriceMinimum::new(gas_price_minimum, self.client.clone())
+            .get_gas_price_minimum(address)
+            .call()
+            .await?;
+        Ok(Some(FeeCurrency {
+            address,
+            gas_price: gas_price_minimum * 2,
+        }))
+    }
+
+    async fn erc20_token(&self, name: &str) -> Result<Erc20<M>> {
+        let address = self.registry_lookup(name).await?;
+        Ok(Erc20::new(address, self.client.clone()))
+    }
+
+    async fn exchange_contract(&self, name: &str) -> Result<Exchange<M>> {
+        let address = self.registry_lookup(name).await?;
+        Ok(Exchange::new(address, self.client.clone()))
+    }
+}
+
+impl<M: Middleware> From<M> for CeloClient<M> {
+    fn from(provider: M) -> Self {
+        CeloClient::new(Arc::new(provider))
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::{anyhow, Result};
+use celophane::Network;
+use ethers::types::Address;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Defaults of the command line options, which take precedence when given.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub network: Option<String>,
+    pub endpoint: Option<Url>,
+    /// Output format: "text", "json" or "csv".
+    pub output: Option<String>,
+    /// Account the account commands read when given none.
+    pub account: Option<Address>,
+    /// Token to pay transaction fees in.
+    pub fee_currency: Option<String>,
+}
+
+impl Profile {
+    /// `self`, with the settings it leaves out taken from `defaults`.
+    fn or(self, defaults: &Profile) -> Profile {
+        Profile {
+            network: self.network.or_else(|| defaults.network.clone()),
+            endpoint: self.endpoint.or_else(|| defaults.endpoint.clone()),
+            output: self.output.or_else(|| defaults.output.clone()),
+            account: self.account.or(defaults.account),
+            fee_currency: self.fee_currency.or_else(|| defaults.fee_currency.clone()),
+        }
+    }
+}
+
+/// Settings read from the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Settings used with every profile, unless the profile overrides them.
+    pub defaults: Profile,
+    /// Named profiles, selected with `--profile`.
+    pub profiles: BTreeMap<String, Profile>,
+    /// Accounts by name, usable wherever an account address is expected.
+    pub address_book: BTreeMap<String, Address>,
+    /// Custom networks by name, replacing the built-in network of the same
+    /// name if any.
+    pub networks: BTreeMap<String, Network>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/celophane/config.toml`, where `$XDG_CONFIG_HOME`
+    /// defaults to `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_dir.join("celophane").join("config.toml"))
+    }
+
+    /// Loads the configuration from `path`, or from the default path if none
+    /// is given. Only an explicitly given file is required to exist.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Self::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents)
+                .map_err(|e| anyhow!("Invalid configuration file {}: {}", path.display(), e)),
+            Err(err) if err.kind() == ErrorKind::NotFound && !required => Ok(Config::default()),
+            Err(err) => Err(anyhow!(
+                "Cannot read configuration file {}: {}",
+                path.display(),
+                err
+            )),
+        }
+    }
+
+    /// The settings of the profile called `name`, or the default ones.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile> {
+        match name {
+            Some(name) => match self.profiles.get(name) {
+                Some(profile) => Ok(profile.clone().or(&self.defaults)),
+                None => Err(anyhow!("Unknown profile \"{}\"", name)),
+            },
+            None => Ok(self.defaults.clone()),
+        }
+    }
+
+    /// The network called `name`, from the configuration file or built in.
+    pub fn network(&self, name: &str) -> Result<Network> {
+        self.networks
+            .get(name)
+            .cloned()
+            .or_else(|| Network::builtin(name))
+            .ok_or_else(|| anyhow!("Unknown network \"{}\"", name))
+    }
+}
@@ -0,0 +1,84 @@
+use crate::amount::TokenAmount;
+use ethers::contract::ContractError;
+use ethers::providers::Middleware;
+use thiserror::Error;
+
+/// Errors raised while interacting with the Celo core contracts.
+#[derive(Debug, Error)]
+pub enum CelophaneError {
+    /// The registry has no address for the requested identifier, i.e. the
+    /// contract is not deployed on this network.
+    #[error("no registry entry for \"{name}\"")]
+    RegistryEntryMissing { name: String },
+
+    /// The node could not be reached or returned a transport-level error.
+    #[error("transport error: {0}")]
+    Transport(String),
+
+    /// The node executed the call and the contract reverted.
+    #[error("contract reverted: {0}")]
+    ContractRevert(String),
+
+    /// The call returned data that does not match the ABI.
+    #[error("ABI decode failure: {0}")]
+    Decode(String),
+
+    /// The token symbol is not one of the registry tokens.
+    #[error("unknown token \"{0}\"")]
+    UnknownToken(String),
+
+    /// A token amount could not be parsed.
+    #[error("invalid amount \"{0}\"")]
+    InvalidAmount(String),
+
+    /// The token has more decimals than its amounts can be scaled by.
+    #[error(
+        "{symbol} has {decimals} decimals, more than the {} supported",
+        TokenAmount::MAX_DECIMALS
+    )]
+    UnsupportedDecimals { symbol: String, decimals: u8 },
+
+    /// The registry cache file could not be read or written.
+    #[error("registry cache: {0}")]
+    Cache(String),
+
+    /// The node has no block matching the request.
+    #[error("{0}")]
+    BlockNotFound(String),
+
+    /// The node is on another chain than the network it was expected on.
+    #[error("connected to chain {actual}, expected chain {expected}")]
+    WrongChain { expected: u64, actual: u64 },
+}
+
+pub type Result<T> = std::result::Result<T, CelophaneError>;
+
+impl CelophaneError {
+    /// Classifies an error returned by the node.
+    pub fn from_node<E: std::fmt::Display>(err: E) -> Self {
+        let message = err.to_string();
+        // Nodes report reverts as JSON-RPC errors, so they are only
+        // distinguishable from transport failures by their message.
+        if message.contains("revert") {
+            CelophaneError::ContractRevert(message)
+        } else {
+            CelophaneError::Transport(message)
+        }
+    }
+}
+
+impl<M: Middleware> From<ContractError<M>> for CelophaneError {
+    fn from(err: ContractError<M>) -> Self {
+        match err {
+            ContractError::DecodingError(_)
+            | ContractError::AbiError(_)
+            | ContractError::DetokenizationError(_) => CelophaneError::Decode(err.to_string()),
+            ContractError::MiddlewareError(_) | ContractError::ProviderError(_) => {
+                CelophaneError::from_node(err)
+            }
+            ContractError::ConstructorError | ContractError::ContractNotDeployed => {
+                CelophaneError::ContractRevert(err.to_string())
+            }
+        }
+    }
+}
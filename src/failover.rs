@@ -0,0 +1,237 @@
+use crate::transport::{BatchClient, JsonRpcError, Transport, TransportError};
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, ProviderError};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use thiserror::Error;
+use url::Url;
+
+/// JSON-RPC error code some providers answer rate limited requests with.
+const HTTP_TOO_MANY_REQUESTS: i64 = 429;
+/// JSON-RPC error code of a request exceeding the node's limits (EIP-1474).
+const LIMIT_EXCEEDED: i64 = -32005;
+
+/// How a [`FailoverClient`] retries failed requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of times a request is retried before giving up.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on every following one.
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(8),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `attempt`, counting from zero.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt);
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+}
+
+/// A node endpoint of a [`FailoverClient`].
+#[derive(Debug)]
+pub struct Endpoint {
+    pub url: Url,
+    pub transport: Transport,
+}
+
+impl Endpoint {
+    pub fn new(url: Url, transport: Transport) -> Self {
+        Endpoint { url, transport }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum FailoverError {
+    /// The request failed on the last endpoint tried.
+    #[error("{endpoint}: {source}")]
+    Endpoint {
+        endpoint: Url,
+        source: Box<TransportError>,
+    },
+
+    /// The request parameters could not be serialized.
+    #[error(transparent)]
+    Params(#[from] serde_json::Error),
+}
+
+impl From<FailoverError> for ProviderError {
+    fn from(src: FailoverError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(src))
+    }
+}
+
+/// Sends JSON-RPC requests to one of several endpoints, moving on to the next
+/// one when a request fails with a transport error or is rate limited.
+///
+/// Failed requests are retried with exponential backoff, except for those
+/// sending transactions, which are not idempotent.
+#[derive(Debug)]
+pub struct FailoverClient {
+    endpoints: Vec<Endpoint>,
+    /// Index of the endpoint requests are sent to.
+    current: AtomicUsize,
+    policy: RetryPolicy,
+    verbose: bool,
+}
+
+impl FailoverClient {
+    /// Panics if `endpoints` is empty.
+    pub fn new(endpoints: Vec<Endpoint>) -> Self {
+        assert!(!endpoints.is_empty(), "no endpoint given");
+        FailoverClient {
+            endpoints,
+            current: AtomicUsize::new(0),
+            policy: RetryPolicy::default(),
+            verbose: false,
+        }
+    }
+
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Reports on stderr which endpoint served each request, and why requests
+    /// were retried.
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// URL of the endpoint requests are currently sent to.
+    pub fn current_endpoint(&self) -> &Url {
+        &self.endpoints[self.current.load(Ordering::SeqCst)].url
+    }
+
+    /// Moves on from the endpoint at `index`, unless a concurrent request
+    /// already did.
+    fn fail_over(&self, index: usize) {
+        let next = (index + 1) % self.endpoints.len();
+        let _ = self
+            .current
+            .compare_exchange(index, next, Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
+
+impl FailoverClient {
+    /// Sends a request with `send`, retrying it on the next endpoints if it
+    /// fails and `retry` allows.
+    async fn send_with_retries<'a, R, F, Fut>(
+        &'a self,
+        method: &str,
+        retry: bool,
+        send: F,
+    ) -> Result<R, FailoverError>
+    where
+        F: Fn(&'a Transport) -> Fut,
+        Fut: Future<Output = Result<R, TransportError>>,
+    {
+        let max_retries = if retry { self.policy.max_retries } else { 0 };
+
+        let mut attempt = 0;
+        loop {
+            let index = self.current.load(Ordering::SeqCst);
+            let endpoint = &self.endpoints[index];
+            // The result is not Send, so it must not be held across the sleep.
+            let err = match send(&endpoint.transport).await {
+                Ok(result) => {
+                    if self.verbose {
+                        eprintln!("{} served by {}", method, endpoint.url);
+                    }
+                    return Ok(result);
+                }
+                Err(err) => err,
+            };
+            if attempt >= max_retries || !is_retryable(&err) {
+                return Err(FailoverError::Endpoint {
+                    endpoint: endpoint.url.clone(),
+                    source: Box::new(err),
+                });
+            }
+
+            let backoff = self.policy.backoff(attempt);
+            if self.verbose {
+                eprintln!(
+                    "{} failed on {}, retrying in {:?}: {}",
+                    method, endpoint.url, backoff, err
+                );
+            }
+            self.fail_over(index);
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for FailoverClient {
+    type Error = FailoverError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: Serialize + DeserializeOwned,
+    {
+        // Serialized once, to be sent again on retries.
+        let params = serde_json::to_value(params)?;
+        self.send_with_retries(method, is_idempotent(method), |transport| {
+            transport.request(method, &params)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl BatchClient for FailoverClient {
+    async fn request_batch(
+        &self,
+        requests: &[(&str, Value)],
+    ) -> Result<Vec<Result<Value, JsonRpcError>>, ProviderError> {
+        let retry = requests.iter().all(|(method, _)| is_idempotent(method));
+        let result = self
+            .send_with_retries("batch", retry, |transport| {
+                transport.request_batch(requests)
+            })
+            .await;
+        Ok(result?)
+    }
+}
+
+/// Whether sending `method` twice has the same effect as sending it once.
+fn is_idempotent(method: &str) -> bool {
+    !matches!(method, "eth_sendTransaction" | "eth_sendRawTransaction")
+}
+
+/// Whether `err` may not happen again: a transport failure, or the node
+/// turning the request down because of a rate limit.
+fn is_retryable(err: &TransportError) -> bool {
+    match err {
+        // Retrying would fail the same way.
+        TransportError::BatchUnsupported | TransportError::InvalidBatchResponse(_) => false,
+        // Other JSON-RPC errors come from a node that processed the request,
+        // e.g. a revert, and would be returned again.
+        err => match err.json_rpc_code() {
+            Some(code) => code == HTTP_TOO_MANY_REQUESTS || code == LIMIT_EXCEEDED,
+            None => true,
+        },
+    }
+}
@@ -0,0 +1,154 @@
+use async_trait::async_trait;
+use ethers::providers::{FromErr, JsonRpcClient, Middleware, PendingTransaction, ProviderError};
+use ethers::types::{Address, BlockNumber, TransactionRequest, U256};
+use serde_json::Value;
+use std::sync::RwLock;
+use thiserror::Error;
+
+/// Token transaction fees are paid in, along with the gas price quoted in it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeeCurrency {
+    pub address: Address,
+    pub gas_price: U256,
+}
+
+/// Populates the Celo-specific transaction fields: pays fees in a fee currency
+/// other than CELO and optionally pays a gateway fee.
+///
+/// Fees are paid in CELO until a fee currency is set. This middleware must sit
+/// above the signer, so that the fields are part of the signed transaction.
+#[derive(Debug)]
+pub struct FeeCurrencyMiddleware<M> {
+    inner: M,
+    sender: Option<Address>,
+    fee_currency: RwLock<Option<FeeCurrency>>,
+    gateway_fee: Option<(Address, U256)>,
+}
+
+#[derive(Debug, Error)]
+pub enum FeeCurrencyMiddlewareError<M: Middleware> {
+    #[error("{0}")]
+    MiddlewareError(M::Error),
+
+    #[error(transparent)]
+    ProviderError(ProviderError),
+}
+
+impl<M: Middleware> FromErr<M::Error> for FeeCurrencyMiddlewareError<M> {
+    fn from(src: M::Error) -> Self {
+        FeeCurrencyMiddlewareError::MiddlewareError(src)
+    }
+}
+
+impl<M: Middleware> FeeCurrencyMiddleware<M> {
+    pub fn new(inner: M) -> Self {
+        FeeCurrencyMiddleware {
+            inner,
+            sender: None,
+            fee_currency: RwLock::new(None),
+            gateway_fee: None,
+        }
+    }
+
+    /// Account transactions are sent from, used for gas estimation.
+    pub fn with_sender(mut self, sender: Address) -> Self {
+        self.sender = Some(sender);
+        self
+    }
+
+    /// Pays `fee` (in the fee currency) to `recipient` with every transaction.
+    pub fn with_gateway_fee(mut self, recipient: Address, fee: U256) -> Self {
+        self.gateway_fee = Some((recipient, fee));
+        self
+    }
+
+    /// Sets the currency fees are paid in, `None` for CELO.
+    pub fn set_fee_currency(&self, fee_currency: Option<FeeCurrency>) {
+        *self.fee_currency.write().unwrap() = fee_currency;
+    }
+
+    pub fn fee_currency(&self) -> Option<FeeCurrency> {
+        *self.fee_currency.read().unwrap()
+    }
+
+    fn fill_transaction(&self, tx: &mut TransactionRequest) {
+        if tx.from.is_none() {
+            tx.from = self.sender;
+        }
+        if let Some(fee_currency) = self.fee_currency() {
+            if tx.fee_currency.is_none() {
+                tx.fee_currency = Some(fee_currency.address);
+            }
+            if tx.gas_price.is_none() {
+                tx.gas_price = Some(fee_currency.gas_price);
+            }
+        }
+        if let Some((recipient, fee)) = self.gateway_fee {
+            if tx.gateway_fee_recipient.is_none() {
+                tx.gateway_fee_recipient = Some(recipient);
+                tx.gateway_fee = Some(fee);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<M: Middleware> Middleware for FeeCurrencyMiddleware<M> {
+    type Error = FeeCurrencyMiddlewareError<M>;
+    type Provider = M::Provider;
+    type Inner = M;
+
+    fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    async fn get_gas_price(&self) -> Result<U256, Self::Error> {
+        match self.fee_currency() {
+            Some(fee_currency) => Ok(fee_currency.gas_price),
+            None => self.inner.get_gas_price().await.map_err(FromErr::from),
+        }
+    }
+
+    async fn estimate_gas(&self, tx: &TransactionRequest) -> Result<U256, Self::Error> {
+        let mut tx = tx.clone();
+        self.fill_transaction(&mut tx);
+
+        // `TransactionRequest` serializes the Celo fields in snake case, but
+        // nodes only read them in camel case.
+        let mut params = serde_json::to_value(&tx)
+            .map_err(|e| FeeCurrencyMiddlewareError::ProviderError(e.into()))?;
+        if let Value::Object(fields) = &mut params {
+            let renames = [
+                ("fee_currency", "feeCurrency"),
+                ("gateway_fee_recipient", "gatewayFeeRecipient"),
+                ("gateway_fee", "gatewayFee"),
+            ];
+            for (from, to) in renames.iter() {
+                if let Some(value) = fields.remove(*from) {
+                    fields.insert(to.to_string(), value);
+                }
+            }
+        }
+
+        self.provider()
+            .as_ref()
+            .request("eth_estimateGas", [params])
+            .await
+            .map_err(|e| FeeCurrencyMiddlewareError::ProviderError(e.into()))
+    }
+
+    async fn send_transaction<'a>(
+        &'a self,
+        mut tx: TransactionRequest,
+        block: Option<BlockNumber>,
+    ) -> Result<PendingTransaction<'a, Self::Provider>, Self::Error> {
+        self.fill_transaction(&mut tx);
+        if tx.gas.is_none() {
+            tx.gas = Some(self.estimate_gas(&tx).await?);
+        }
+        self.inner
+            .send_transaction(tx, block)
+            .await
+            .map_err(FeeCurrencyMiddlewareError::MiddlewareError)
+    }
+}
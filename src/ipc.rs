@@ -0,0 +1,175 @@
+use crate::transport::{JsonRpcError, Response};
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, ProviderError};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use std::fmt::Debug;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::sync::Mutex;
+
+#[derive(Debug, Error)]
+pub enum IpcError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    JsonRpcError(#[from] JsonRpcError),
+
+    #[error("connection closed by the node")]
+    Closed,
+}
+
+impl From<IpcError> for ProviderError {
+    fn from(src: IpcError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(src))
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    stream: UnixStream,
+    /// Bytes read past the last response.
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    fn new(stream: UnixStream) -> Self {
+        Connection {
+            stream,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Reads the first value sent by the node that `matches`, skipping
+    /// anything else, e.g. subscription notifications.
+    async fn read_value<F: Fn(&Value) -> bool>(&mut self, matches: F) -> Result<Value, IpcError> {
+        loop {
+            // Responses are not delimited, so they are split by parsing.
+            let mut values =
+                serde_json::Deserializer::from_slice(&self.buffer).into_iter::<Value>();
+            match values.next() {
+                Some(Ok(value)) => {
+                    let end = values.byte_offset();
+                    self.buffer.drain(..end);
+                    if matches(&value) {
+                        return Ok(value);
+                    }
+                    continue;
+                }
+                Some(Err(err)) if !err.is_eof() => return Err(err.into()),
+                // Nothing or only part of a response was read yet.
+                _ => {}
+            }
+
+            let mut chunk = [0; 4096];
+            let read = self.stream.read(&mut chunk).await?;
+            if read == 0 {
+                return Err(IpcError::Closed);
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+/// A JSON-RPC client over the IPC socket of a local node.
+///
+/// Requests are sent one at a time over a single connection, which is
+/// reopened on the next request if one fails or is cancelled.
+#[derive(Debug)]
+pub struct Ipc {
+    path: PathBuf,
+    id: AtomicU64,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl Ipc {
+    /// Connects to the socket at `path`.
+    pub async fn connect<P: AsRef<Path>>(path: P) -> Result<Self, IpcError> {
+        let path = path.as_ref().to_path_buf();
+        let stream = UnixStream::connect(&path).await?;
+        Ok(Ipc {
+            path,
+            id: AtomicU64::new(0),
+            connection: Mutex::new(Some(Connection::new(stream))),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Sends `requests`, complete JSON-RPC request objects, as a single batch
+    /// and returns the node's answer: an array of responses, or one error if
+    /// the node rejected the batch as a whole.
+    pub async fn request_batch(&self, requests: &[Value]) -> Result<Value, IpcError> {
+        let payload = serde_json::to_vec(requests)?;
+        self.exchange(&payload, |value| {
+            value.is_array()
+                || (value.get("id") == Some(&Value::Null) && value.get("error").is_some())
+        })
+        .await
+    }
+
+    /// Sends `payload` and reads the first value that `matches` in return.
+    async fn exchange<F: Fn(&Value) -> bool>(
+        &self,
+        payload: &[u8],
+        matches: F,
+    ) -> Result<Value, IpcError> {
+        let mut guard = self.connection.lock().await;
+        // Taken out for the duration of the exchange, so that a request
+        // cancelled halfway, e.g. by a timeout, drops the connection instead
+        // of leaving a partial response in it for the next request.
+        let mut connection = match guard.take() {
+            Some(connection) => connection,
+            None => Connection::new(UnixStream::connect(&self.path).await?),
+        };
+        connection.stream.write_all(payload).await?;
+        let value = connection.read_value(matches).await?;
+        *guard = Some(connection);
+        Ok(value)
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for Ipc {
+    type Error = IpcError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, IpcError>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: Serialize + DeserializeOwned,
+    {
+        let id = self.id.fetch_add(1, Ordering::SeqCst) + 1;
+        let params = match serde_json::to_value(params)? {
+            Value::Null => json!([]),
+            params => params,
+        };
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+        let payload = serde_json::to_vec(&request)?;
+
+        let response = self
+            .exchange(&payload, |value| {
+                value.get("id").and_then(Value::as_u64) == Some(id)
+            })
+            .await?;
+        let response: Response = serde_json::from_value(response)?;
+        if let Some(error) = response.error {
+            return Err(error.into());
+        }
+        Ok(serde_json::from_value(response.result)?)
+    }
+}
@@ -0,0 +1,28 @@
+//! A thin [Celo](https://celo.org/) wrapper over [ethers-rs](https://github.com/gakonst/ethers-rs).
+
+mod amount;
+pub mod celo;
+mod client;
+mod error;
+pub mod failover;
+pub mod fee_currency;
+#[cfg(unix)]
+pub mod ipc;
+pub mod mento;
+pub mod multicall;
+pub mod network;
+pub mod registry;
+pub mod throttle;
+pub mod transport;
+
+pub use amount::TokenAmount;
+pub use client::{CeloClient, FeeEstimate};
+pub use error::{CelophaneError, Result};
+pub use failover::{FailoverClient, RetryPolicy};
+pub use fee_currency::{FeeCurrency, FeeCurrencyMiddleware};
+pub use mento::{ExchangeEvent, ExchangeLog, ExchangeState, MentoPool};
+pub use multicall::CallBatch;
+pub use network::Network;
+pub use registry::RegistryCache;
+pub use throttle::ThrottledClient;
+pub use transport::{BatchClient, Transport};
@@ -1,47 +1,548 @@
 use anyhow::{anyhow, Result};
-use ethers::providers::{Http, Middleware, Provider, Ws};
-use ethers::types::{Address, U256};
-use std::convert::TryFrom;
-use std::future::Future;
+use celophane::celo::{self, ExchangePool, Token, TokenMetadata, TokenSpec};
+use celophane::failover::Endpoint;
+use celophane::mento::{self, ExchangeEvent, ExchangeState, MentoPool};
+use celophane::{
+    registry, BatchClient, CeloClient, CelophaneError, FailoverClient, FeeCurrencyMiddleware,
+    Network, RegistryCache, ThrottledClient, TokenAmount, Transport,
+};
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+use config::{Config, Profile};
+use ethers::contract::builders::ContractCall;
+use ethers::core::abi::Detokenize;
+use ethers::middleware::SignerMiddleware;
+use ethers::providers::{Middleware, Provider, Ws};
+use ethers::signers::LocalWallet;
+use ethers::types::{Address, BlockNumber, TransactionRequest, H256, U256, U64};
+use futures_util::future::join_all;
+use futures_util::stream::{self, StreamExt};
+use output::{print_records, OutputFormat, Record, RecordStream};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Read};
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 use structopt::StructOpt;
-use tokio::join;
 use url::Url;
 
-mod celo;
+mod config;
+mod output;
 
 #[derive(StructOpt)]
 struct CelophaneOpt {
-    /// Endpoint to connect to.
-    #[structopt(long, default_value = "http://localhost:8545")]
-    endpoint: Url,
+    /// Network to connect to: mainnet, alfajores, baklava, local or a network
+    /// of the configuration file (defaults to local).
+    #[structopt(long)]
+    network: Option<String>,
+
+    /// Endpoint to connect to (defaults to the network's): an HTTP or
+    /// WebSocket URL, or the IPC socket of a local node, e.g.
+    /// ipc:///path/to/geth.ipc or just its path. May be repeated to fail over
+    /// to the next endpoint when one is unavailable.
+    #[structopt(
+        long = "endpoint",
+        number_of_values = 1,
+        parse(try_from_str = celophane::transport::endpoint_url)
+    )]
+    endpoints: Vec<Url>,
+
+    /// Report which endpoint serves each request.
+    #[structopt(short, long)]
+    verbose: bool,
+
+    /// Seconds to wait for a node request, retries included, or 0 to wait
+    /// indefinitely.
+    #[structopt(long, default_value = "30")]
+    timeout: u64,
+
+    /// Maximum number of node requests in flight at once.
+    #[structopt(long)]
+    max_concurrency: Option<NonZeroUsize>,
+
+    /// Maximum number of node requests started per second.
+    #[structopt(long)]
+    requests_per_second: Option<NonZeroU32>,
+
+    /// Configuration file (defaults to ~/.config/celophane/config.toml).
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Profile of the configuration file to take defaults from.
+    #[structopt(long)]
+    profile: Option<String>,
+
+    /// File to cache registry addresses in across invocations.
+    #[structopt(long, parse(from_os_str))]
+    registry_cache: Option<PathBuf>,
+
+    /// Block number to resolve registry addresses at.
+    #[structopt(long, requires = "registry-cache")]
+    registry_block: Option<u64>,
+
+    /// Seconds registry addresses resolved at the latest block stay cached
+    /// for, 0 to resolve them again on every invocation.
+    #[structopt(long, default_value = "3600")]
+    registry_max_age: u64,
+
+    /// Address of a Multicall contract to aggregate read calls through
+    /// (defaults to the network's).
+    #[structopt(long)]
+    multicall: Option<Address>,
+
+    /// Read and print token amounts in base units (wei) instead of decimals.
+    #[structopt(long)]
+    raw: bool,
+
+    /// Output format (defaults to text).
+    #[structopt(long, possible_values = OutputFormat::VARIANTS)]
+    output: Option<OutputFormat>,
+
+    /// Block to run read commands against: a number, a hash, "latest" or
+    /// "pending".
+    #[structopt(long, conflicts_with = "at")]
+    block: Option<BlockSpec>,
+
+    /// Run read commands against the last block mined at or before this RFC
+    /// 3339 timestamp, e.g. 2021-03-31T23:59:59Z.
+    #[structopt(long, parse(try_from_str = DateTime::parse_from_rfc3339))]
+    at: Option<DateTime<FixedOffset>>,
 
     #[structopt(subcommand)]
     cmd: Command,
 }
 
+/// A block given by number, hash or tag.
+#[derive(Clone, Copy, Debug)]
+enum BlockSpec {
+    Number(u64),
+    Hash(H256),
+    Latest,
+    Pending,
+}
+
+impl FromStr for BlockSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "latest" => Ok(BlockSpec::Latest),
+            "pending" => Ok(BlockSpec::Pending),
+            _ if s.starts_with("0x") && s.len() == 66 => Ok(BlockSpec::Hash(s.parse()?)),
+            _ => s
+                .parse()
+                .map(BlockSpec::Number)
+                .map_err(|_| anyhow!("Invalid block \"{}\"", s)),
+        }
+    }
+}
+
+/// An account given by address or by name in the address book.
+#[derive(Clone, Debug)]
+enum AccountRef {
+    Address(Address),
+    Name(String),
+}
+
+impl FromStr for AccountRef {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.starts_with("0x") {
+            s.parse()
+                .map(AccountRef::Address)
+                .map_err(|_| anyhow!("Invalid address \"{}\"", s))
+        } else if s.is_empty() {
+            Err(anyhow!("Invalid account \"\""))
+        } else {
+            Ok(AccountRef::Name(s.to_string()))
+        }
+    }
+}
+
 #[derive(StructOpt)]
 struct AccountBalanceOpt {
-    address: Address,
+    /// Accounts to retrieve the balances of, by address or address book name
+    /// (defaults to the profile's account).
+    addresses: Vec<AccountRef>,
+
+    /// File to read more accounts from, one per line ("-" for stdin).
+    #[structopt(long, parse(from_os_str))]
+    file: Option<PathBuf>,
+
+    /// Number of accounts whose balances are read in a single batch.
+    #[structopt(long, default_value = "100")]
+    batch_size: usize,
+
+    /// Maximum number of batches to query at once. Without Multicall or
+    /// JSON-RPC batches, each batch sends at most 16 calls at a time.
+    #[structopt(long, default_value = "4")]
+    concurrency: usize,
+
+    /// Token to read the balances of: a contract address, a registry token
+    /// symbol or a registry identifier. May be repeated (defaults to CELO, cUSD
+    /// and cEUR).
+    #[structopt(long = "token", number_of_values = 1)]
+    tokens: Vec<TokenSpec>,
+}
+
+#[derive(StructOpt)]
+struct SignerOpt {
+    /// Hex-encoded private key to sign transactions with.
+    #[structopt(
+        long,
+        env = "CELOPHANE_PRIVATE_KEY",
+        hide_env_values = true,
+        conflicts_with = "keystore"
+    )]
+    private_key: Option<String>,
+
+    /// Encrypted JSON keystore file to sign transactions with.
+    #[structopt(long, parse(from_os_str))]
+    keystore: Option<PathBuf>,
+
+    /// Password of the keystore.
+    #[structopt(long, env = "CELOPHANE_KEYSTORE_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+}
+
+impl SignerOpt {
+    fn wallet(&self) -> Result<LocalWallet> {
+        match (&self.private_key, &self.keystore) {
+            (Some(key), None) => {
+                let key = key.trim_start_matches("0x");
+                if key.len() != 64 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(anyhow!("Invalid private key"));
+                }
+                key.parse().map_err(|_| anyhow!("Invalid private key"))
+            }
+            (None, Some(path)) => {
+                let password = self
+                    .password
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("--password is required to decrypt a keystore"))?;
+                Ok(LocalWallet::decrypt_keystore(path, password)?)
+            }
+            _ => Err(anyhow!("Either --private-key or --keystore is required")),
+        }
+    }
+}
+
+#[derive(StructOpt)]
+struct TransactionOpt {
+    #[structopt(flatten)]
+    signer: SignerOpt,
+
+    /// Token to pay transaction fees in (defaults to CELO).
+    #[structopt(long, possible_values = Token::SYMBOLS, case_insensitive = true)]
+    fee_currency: Option<Token>,
+
+    /// Recipient of the gateway fee.
+    #[structopt(long, requires = "gateway-fee")]
+    gateway_fee_recipient: Option<Address>,
+
+    /// Gateway fee paid with every transaction, in the fee currency.
+    #[structopt(long, requires = "gateway-fee-recipient")]
+    gateway_fee: Option<String>,
+
+    /// Number of confirmations to wait for (0 to return once the transaction is sent).
+    #[structopt(long, default_value = "1")]
+    confirmations: usize,
+
+    /// Only estimate the transaction fees, without sending anything.
+    #[structopt(long)]
+    dry_run: bool,
+}
+
+#[derive(StructOpt)]
+struct AccountTransferOpt {
+    /// Recipient, by address or address book name.
+    #[structopt(long)]
+    to: AccountRef,
+
+    /// Amount to send.
+    #[structopt(long)]
+    amount: String,
+
+    /// Token to send: a contract address, a registry token symbol or a registry
+    /// identifier.
+    #[structopt(long, default_value = "CELO")]
+    token: TokenSpec,
+
+    #[structopt(flatten)]
+    tx: TransactionOpt,
+}
+
+#[derive(StructOpt)]
+struct AccountHistoryOpt {
+    /// Account to list the transfers of (defaults to the profile's account).
+    address: Option<AccountRef>,
+
+    /// Token to list the transfers of: a contract address, a registry token
+    /// symbol or a registry identifier.
+    #[structopt(long, default_value = "cUSD")]
+    token: TokenSpec,
+
+    /// First block to read transfers from.
+    #[structopt(long, default_value = "0")]
+    from_block: u64,
+
+    /// Last block to read transfers from (defaults to the latest block).
+    #[structopt(long)]
+    to_block: Option<u64>,
+
+    /// Maximum number of blocks to request logs for at once.
+    #[structopt(long, default_value = "10000")]
+    page_size: u64,
+}
+
+#[derive(StructOpt)]
+struct AccountAllowanceOpt {
+    owner: AccountRef,
+
+    spender: AccountRef,
+
+    /// Token to check the allowance of: a contract address, a registry token
+    /// symbol or a registry identifier.
+    #[structopt(long, default_value = "CELO")]
+    token: TokenSpec,
+}
+
+#[derive(StructOpt)]
+struct AccountApproveOpt {
+    spender: AccountRef,
+
+    /// Amount the spender may transfer, or "max" for an unlimited allowance.
+    amount: String,
+
+    /// Token to approve: a contract address, a registry token
+    /// symbol or a registry identifier.
+    #[structopt(long, default_value = "CELO")]
+    token: TokenSpec,
+
+    #[structopt(flatten)]
+    tx: TransactionOpt,
+}
+
+#[derive(StructOpt)]
+struct AccountRevokeOpt {
+    spender: AccountRef,
+
+    /// Token to revoke the allowance of: a contract address, a registry token
+    /// symbol or a registry identifier.
+    #[structopt(long, default_value = "CELO")]
+    token: TokenSpec,
+
+    #[structopt(flatten)]
+    tx: TransactionOpt,
+}
+
+#[derive(StructOpt)]
+struct AccountAllowancesOpt {
+    /// Account to scan the approvals of (defaults to the profile's account).
+    owner: Option<AccountRef>,
+
+    /// Only scan this token: a contract address, a registry token symbol or a
+    /// registry identifier. May be repeated (defaults to CELO, cUSD and cEUR).
+    #[structopt(long = "token", number_of_values = 1)]
+    tokens: Vec<TokenSpec>,
+
+    /// First block to read approvals from.
+    #[structopt(long, default_value = "0")]
+    from_block: u64,
+
+    /// Last block to read approvals from (defaults to the latest block).
+    #[structopt(long)]
+    to_block: Option<u64>,
+
+    /// Maximum number of blocks to request logs for at once.
+    #[structopt(long, default_value = "10000")]
+    page_size: u64,
+
+    /// Also list spenders whose allowance is zero, e.g. revoked approvals.
+    #[structopt(long)]
+    all: bool,
 }
 
 #[derive(StructOpt)]
 enum AccountCommand {
     /// Retrieve an account's balance.
     Balance(AccountBalanceOpt),
+    /// Send CELO or an ERC-20 token to another account.
+    Transfer(AccountTransferOpt),
+    /// List an account's token transfers with its running balance, between
+    /// its opening and closing `balanceOf`.
+    History(AccountHistoryOpt),
+    /// Show how much a spender may transfer on behalf of an owner.
+    Allowance(AccountAllowanceOpt),
+    /// Allow a spender to transfer tokens on your behalf.
+    Approve(AccountApproveOpt),
+    /// Set a spender's allowance back to zero.
+    Revoke(AccountRevokeOpt),
+    /// List the spenders an owner approved that still have an allowance.
+    Allowances(AccountAllowancesOpt),
 }
 
 #[derive(StructOpt)]
 struct ExchangeShowOpt {
-    /// Amount (base) to report quote amounts on.
-    #[structopt(long, default_value = "1000000000000000000", parse(try_from_str=U256::from_dec_str))]
-    amount: U256,
+    /// Amount (base) to report quote amounts on (defaults to one token).
+    #[structopt(long)]
+    amount: Option<String>,
+
+    /// Only show the pool of this stable token (defaults to all pools).
+    #[structopt(long)]
+    stable: Option<String>,
+}
+
+#[derive(StructOpt)]
+struct ExchangeInfoOpt {
+    /// Only show the pool of this stable token (defaults to all pools).
+    #[structopt(long)]
+    stable: Option<String>,
+}
+
+#[derive(StructOpt)]
+struct ExchangeLadderOpt {
+    /// Comma-separated trade sizes to quote.
+    #[structopt(long, use_delimiter = true, default_value = "1,10,100,1000,10000")]
+    sizes: Vec<String>,
+
+    /// Only quote the pool of this stable token (defaults to all pools).
+    #[structopt(long)]
+    stable: Option<String>,
+
+    /// Compute the quotes locally from the buckets instead of calling the
+    /// exchange for each size.
+    #[structopt(long)]
+    local: bool,
+}
+
+#[derive(StructOpt)]
+struct ExchangeTradesOpt {
+    /// First block to read events from.
+    #[structopt(long, default_value = "0")]
+    from_block: u64,
+
+    /// Last block to read events from (defaults to the latest block).
+    #[structopt(long, conflicts_with = "follow")]
+    to_block: Option<u64>,
+
+    /// Only show the events of the pool of this stable token (defaults to all
+    /// pools).
+    #[structopt(long)]
+    stable: Option<String>,
+
+    /// Maximum number of blocks to request logs for at once.
+    #[structopt(long, default_value = "10000")]
+    page_size: u64,
+
+    /// Keep printing new events as blocks are mined. New blocks are
+    /// subscribed to over the first WebSocket endpoint, if any, and polled for
+    /// otherwise.
+    #[structopt(long)]
+    follow: bool,
+
+    /// Seconds to wait between polls for new blocks when following without a
+    /// WebSocket endpoint.
+    #[structopt(long, default_value = "5")]
+    poll_interval: u64,
+}
+
+#[derive(StructOpt)]
+struct ExchangeSellOpt {
+    /// Amount to sell.
+    #[structopt(long)]
+    amount: String,
+
+    /// Token to sell: CELO or a stable token.
+    #[structopt(long)]
+    from: String,
+
+    /// Stable token to receive when selling CELO.
+    #[structopt(long)]
+    stable: Option<String>,
+
+    /// Maximum percentage the received amount may fall short of the quote.
+    #[structopt(long, default_value = "1")]
+    max_slippage: f64,
+
+    #[structopt(flatten)]
+    tx: TransactionOpt,
+}
+
+#[derive(StructOpt)]
+struct ExchangeBuyOpt {
+    /// Amount to buy.
+    #[structopt(long)]
+    amount: String,
+
+    /// Token to buy: CELO or a stable token.
+    #[structopt(long)]
+    to: String,
+
+    /// Stable token to pay with when buying CELO.
+    #[structopt(long)]
+    stable: Option<String>,
+
+    /// Maximum percentage the paid amount may exceed the quote by.
+    #[structopt(long, default_value = "1")]
+    max_slippage: f64,
+
+    #[structopt(flatten)]
+    tx: TransactionOpt,
 }
 
 #[derive(StructOpt)]
 enum ExchangeCommand {
     /// Display the on-chain exchange (Mento) rates.
     Show(ExchangeShowOpt),
+    /// Display the buckets and parameters of the exchange pools.
+    Info(ExchangeInfoOpt),
+    /// Quote a range of trade sizes and their price impact.
+    Ladder(ExchangeLadderOpt),
+    /// List past trades and bucket updates.
+    Trades(ExchangeTradesOpt),
+    /// Sell an amount of CELO or of a stable token on the exchange.
+    Sell(ExchangeSellOpt),
+    /// Buy an amount of CELO or of a stable token on the exchange.
+    Buy(ExchangeBuyOpt),
+}
+
+#[derive(StructOpt)]
+struct RegistryGetOpt {
+    /// Registry identifier, e.g. "StableToken".
+    name: String,
+}
+
+#[derive(StructOpt)]
+struct RegistryHistoryOpt {
+    /// Only show updates of this registry identifier.
+    name: Option<String>,
+
+    /// First block to replay updates from.
+    #[structopt(long, default_value = "0")]
+    from_block: u64,
+
+    /// Last block to replay updates to (defaults to the latest block).
+    #[structopt(long)]
+    to_block: Option<u64>,
+
+    /// Maximum number of blocks to request logs for at once.
+    #[structopt(long, default_value = "10000")]
+    page_size: u64,
+}
+
+#[derive(StructOpt)]
+enum RegistryCommand {
+    /// List the addresses of all core contracts.
+    List,
+    /// Look up the address of a registry identifier.
+    Get(RegistryGetOpt),
+    /// Show when registry entries were pointed to new addresses.
+    History(RegistryHistoryOpt),
 }
 
 #[derive(StructOpt)]
@@ -50,100 +551,2323 @@ enum Command {
     Account(AccountCommand),
     /// On-chain exchange (Mento) interaction.
     Exchange(ExchangeCommand),
+    /// Core contract registry inspection.
+    Registry(RegistryCommand),
+}
+
+impl Command {
+    /// Transaction options of the commands that send transactions.
+    fn transaction(&self) -> Option<&TransactionOpt> {
+        match self {
+            Command::Account(AccountCommand::Transfer(opt)) => Some(&opt.tx),
+            Command::Account(AccountCommand::Approve(opt)) => Some(&opt.tx),
+            Command::Account(AccountCommand::Revoke(opt)) => Some(&opt.tx),
+            Command::Exchange(ExchangeCommand::Sell(opt)) => Some(&opt.tx),
+            Command::Exchange(ExchangeCommand::Buy(opt)) => Some(&opt.tx),
+            _ => None,
+        }
+    }
+
+    /// Whether the command reads a range of blocks, given by `--from-block`
+    /// and `--to-block`, rather than a single block.
+    fn reads_block_range(&self) -> bool {
+        matches!(
+            self,
+            Command::Account(AccountCommand::History(_))
+                | Command::Exchange(ExchangeCommand::Trades(_))
+                | Command::Registry(RegistryCommand::History(_))
+        )
+    }
+}
+
+/// Settings shared by all commands.
+struct Context {
+    /// Read and print amounts in base units.
+    raw: bool,
+    /// Format results are printed in.
+    output: OutputFormat,
+    /// Account sending transactions, for commands that send any.
+    sender: Option<Address>,
+    /// Recipient and amount of the gateway fee paid with every transaction.
+    gateway_fee: Option<(Address, U256)>,
+    /// Exchange pools available to the exchange commands.
+    pools: Vec<ExchangePool>,
+    /// Tokens of the network outside of the registry, by symbol.
+    tokens: BTreeMap<String, Address>,
+    /// Accounts by name.
+    address_book: BTreeMap<String, Address>,
+    /// Account read by the account commands when given none.
+    account: Option<Address>,
+    /// Token transaction fees are paid in.
+    fee_currency: Token,
+    /// Block read commands run against, `None` for the latest block.
+    block: Option<BlockNumber>,
+    /// Endpoints of the node, in order of preference.
+    endpoints: Vec<Url>,
+}
+
+impl Context {
+    /// The context of the command line `args`, with the defaults of `profile`
+    /// and the settings of `network`.
+    fn new(
+        args: &CelophaneOpt,
+        config: &Config,
+        profile: &Profile,
+        network: &Network,
+    ) -> Result<Self> {
+        let output = match (args.output, &profile.output) {
+            (Some(output), _) => output,
+            (None, Some(output)) => output.parse()?,
+            (None, None) => OutputFormat::Text,
+        };
+        let fee_currency = args.cmd.transaction().and_then(|tx| tx.fee_currency);
+        let fee_currency = match (fee_currency, &profile.fee_currency) {
+            (Some(token), _) => token,
+            (None, Some(token)) => token.parse()?,
+            (None, None) => Token::Celo,
+        };
+        // The profile's endpoint belongs to the profile's network, so a network
+        // given on the command line brings its own endpoint.
+        let endpoints = match (&profile.endpoint, &args.network) {
+            _ if !args.endpoints.is_empty() => args.endpoints.clone(),
+            (Some(endpoint), None) => vec![endpoint.clone()],
+            _ => vec![network.endpoint.clone()],
+        };
+        Ok(Context {
+            raw: args.raw,
+            output,
+            sender: None,
+            gateway_fee: None,
+            pools: network.exchange_pools(),
+            tokens: network.tokens.clone(),
+            address_book: config.address_book.clone(),
+            account: profile.account,
+            fee_currency,
+            block: None,
+            endpoints,
+        })
+    }
+
+    /// Decimals amounts of a token with `decimals` are read and printed with,
+    /// where raw amounts have none.
+    fn amount_decimals(&self, decimals: u8) -> u8 {
+        if self.raw {
+            0
+        } else {
+            decimals
+        }
+    }
+
+    /// Formats an amount of one of the registry tokens.
+    fn format_amount(&self, value: U256) -> String {
+        self.format_units(value, celo::TOKEN_DECIMALS)
+    }
+
+    /// Parses an amount of one of the registry tokens.
+    fn parse_amount(&self, amount: &str) -> Result<U256> {
+        self.parse_units(amount, celo::TOKEN_DECIMALS)
+    }
+
+    /// Formats an amount of a token with `decimals`.
+    fn format_units(&self, value: U256, decimals: u8) -> String {
+        TokenAmount::new(value, self.amount_decimals(decimals)).to_string()
+    }
+
+    /// Parses an amount of a token with `decimals`.
+    fn parse_units(&self, amount: &str, decimals: u8) -> Result<U256> {
+        Ok(TokenAmount::parse(amount, self.amount_decimals(decimals))?.value())
+    }
+
+    /// The address of `account`, looking names up in the address book.
+    fn address(&self, account: &AccountRef) -> Result<Address> {
+        match account {
+            AccountRef::Address(address) => Ok(*address),
+            AccountRef::Name(name) => self
+                .address_book
+                .get(name)
+                .copied()
+                .ok_or_else(|| anyhow!("No account \"{}\" in the address book", name)),
+        }
+    }
+
+    /// The address of `account`, or the default account if none is given.
+    fn account_or_default(&self, account: Option<&AccountRef>) -> Result<Address> {
+        match account {
+            Some(account) => self.address(account),
+            None => self.account.ok_or_else(|| anyhow!("No account given")),
+        }
+    }
+
+    /// `spec`, with the network's own tokens resolved to their address.
+    fn token_spec(&self, spec: &TokenSpec) -> TokenSpec {
+        if let TokenSpec::Registry(name) = spec {
+            if let Some(&address) = self.tokens.get(name) {
+                return TokenSpec::Address(address);
+            }
+        }
+        spec.clone()
+    }
+
+    /// Runs `call` against the selected block.
+    fn at_block<M: Middleware, D: Detokenize>(
+        &self,
+        call: ContractCall<M, D>,
+    ) -> ContractCall<M, D> {
+        match self.block {
+            Some(block) => call.block(block),
+            None => call,
+        }
+    }
+
+    /// The exchange pool of the stable token `stable`.
+    fn pool(&self, stable: &str) -> Result<&ExchangePool> {
+        self.pools
+            .iter()
+            .find(|pool| pool.stable.eq_ignore_ascii_case(stable))
+            .ok_or_else(|| anyhow!("No exchange pool for \"{}\"", stable))
+    }
+}
+
+/// Resolves the token `spec` refers to and reads its metadata. Tokens whose
+/// amounts cannot be scaled by their decimals are rejected, unless amounts are
+/// in base units.
+async fn resolve_token<M: Middleware>(
+    client: &CeloClient<M>,
+    spec: &TokenSpec,
+    ctx: &Context,
+) -> celophane::Result<(celo::Erc20<M>, TokenMetadata)> {
+    let token = client.erc20(&ctx.token_spec(spec)).await?;
+    let metadata = TokenMetadata::fetch(&token).await?;
+    if ctx.amount_decimals(metadata.decimals) > TokenAmount::MAX_DECIMALS {
+        return Err(CelophaneError::UnsupportedDecimals {
+            symbol: metadata.symbol,
+            decimals: metadata.decimals,
+        });
+    }
+    Ok((token, metadata))
+}
+
+/// The tokens of `specs`, or the registry tokens if none is given.
+fn token_specs(specs: Vec<TokenSpec>) -> Vec<TokenSpec> {
+    if specs.is_empty() {
+        Token::ALL.iter().map(|&token| token.into()).collect()
+    } else {
+        specs
+    }
+}
+
+/// A token balances are read of, with its metadata or why it could not be
+/// resolved.
+struct BalanceToken<M> {
+    spec: TokenSpec,
+    token: std::result::Result<(celo::Erc20<M>, TokenMetadata), String>,
+}
+
+/// Balances of an account, one per token.
+#[derive(Serialize)]
+struct BalanceRecord {
+    address: Address,
+    balances: Vec<TokenBalance>,
+}
+
+/// Balance of a token, or why it could not be read.
+#[derive(Serialize)]
+struct TokenBalance {
+    /// Contract address of the token, unless it could not be resolved.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<Address>,
+    symbol: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    decimals: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    balance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl TokenBalance {
+    fn new<M>(token: &BalanceToken<M>, balance: BalanceResult, ctx: &Context) -> Self {
+        let mut record = TokenBalance {
+            token: None,
+            symbol: token.spec.to_string(),
+            name: None,
+            decimals: None,
+            balance: None,
+            error: None,
+        };
+        if let Ok((_, metadata)) = &token.token {
+            record.token = Some(metadata.address);
+            record.symbol = metadata.symbol.clone();
+            record.name = Some(metadata.name.clone());
+            record.decimals = Some(metadata.decimals);
+        }
+        match balance {
+            Ok(balance) => {
+                let decimals = record.decimals.unwrap_or(celo::TOKEN_DECIMALS);
+                record.balance = Some(ctx.format_units(balance, decimals));
+            }
+            Err(err) => record.error = Some(err),
+        }
+        record
+    }
+}
+
+impl Record for BalanceRecord {
+    const HEADER: &'static [&'static str] = &["address"];
+
+    /// A balance and an error column per token, e.g. "CELO" and "CELO_error".
+    fn header(&self) -> Vec<String> {
+        let mut header = vec!["address".to_string()];
+        for balance in &self.balances {
+            header.push(balance.symbol.clone());
+            header.push(format!("{}_error", balance.symbol));
+        }
+        header
+    }
+
+    fn fields(&self) -> Vec<String> {
+        let mut fields = vec![format!("{:?}", self.address)];
+        for balance in &self.balances {
+            fields.push(balance.balance.clone().unwrap_or_default());
+            fields.push(balance.error.clone().unwrap_or_default());
+        }
+        fields
+    }
+
+    fn text(&self) -> String {
+        let balances: Vec<String> = self
+            .balances
+            .iter()
+            .map(|balance| match (&balance.balance, &balance.error) {
+                (Some(amount), _) => format!("{} {}", amount, balance.symbol),
+                (None, error) => format!(
+                    "{}: {}",
+                    balance.symbol,
+                    error.as_deref().unwrap_or_default()
+                ),
+            })
+            .collect();
+        format!("{:?}: {}", self.address, balances.join(", "))
+    }
+}
+
+/// A balance, or why it could not be read.
+type BalanceResult = std::result::Result<U256, String>;
+
+/// Balances of each of `tokens` for each of `addresses`, read in a single
+/// batch.
+async fn get_balances<M: Middleware>(
+    client: &CeloClient<M>,
+    tokens: &[BalanceToken<M>],
+    addresses: &[Address],
+    ctx: &Context,
+) -> Vec<BalanceRecord> {
+    let mut calls = Vec::new();
+    for &address in addresses {
+        for token in tokens {
+            if let Ok((token, _)) = &token.token {
+                calls.push(token.balance_of(address));
+            }
+        }
+    }
+    let mut results = client
+        .batch()
+        .block(ctx.block)
+        .call(calls)
+        .await
+        .into_iter();
+
+    let mut records = Vec::with_capacity(addresses.len());
+    for &address in addresses {
+        let mut balances = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            let balance = match &token.token {
+                Ok(_) => results
+                    .next()
+                    .unwrap_or_else(|| Err(CelophaneError::Decode("missing result".to_string())))
+                    .map_err(|err| err.to_string()),
+                Err(err) => Err(err.clone()),
+            };
+            balances.push(TokenBalance::new(token, balance, ctx));
+        }
+        records.push(BalanceRecord { address, balances });
+    }
+    records
+}
+
+/// Reads one account per line from `path`, or from stdin if it is "-".
+/// Blank lines and lines starting with "#" are skipped.
+fn read_accounts(path: &Path) -> Result<Vec<AccountRef>> {
+    let contents = if path == Path::new("-") {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents)?;
+        contents
+    } else {
+        fs::read_to_string(path)?
+    };
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::parse)
+        .collect()
+}
+
+async fn account_balance<M: Middleware>(
+    client: &CeloClient<M>,
+    args: AccountBalanceOpt,
+    ctx: &Context,
+) -> Result<Vec<BalanceRecord>> {
+    let mut accounts = args.addresses;
+    if let Some(path) = &args.file {
+        accounts.extend(read_accounts(path)?);
+    }
+    let addresses = if accounts.is_empty() {
+        vec![ctx.account_or_default(None)?]
+    } else {
+        accounts
+            .iter()
+            .map(|account| ctx.address(account))
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    // Tokens are resolved once, a missing one failing its column of every
+    // account.
+    let specs = token_specs(args.tokens);
+    let resolved = join_all(specs.iter().map(|spec| resolve_token(client, spec, ctx))).await;
+    let tokens: Vec<_> = specs
+        .into_iter()
+        .zip(resolved)
+        .map(|(spec, token)| BalanceToken {
+            spec,
+            token: token.map_err(|err| err.to_string()),
+        })
+        .collect();
+
+    let records = stream::iter(addresses.chunks(args.batch_size.max(1)))
+        .map(|chunk| get_balances(client, &tokens, chunk, ctx))
+        .buffered(args.concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    Ok(records.into_iter().flatten().collect())
+}
+
+#[derive(Serialize)]
+struct TransactionRecord {
+    action: String,
+    fee_currency: &'static str,
+    gas: String,
+    gas_price: String,
+    fee: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transaction_hash: Option<H256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    block_number: Option<u64>,
+}
+
+impl Record for TransactionRecord {
+    const HEADER: &'static [&'static str] = &[
+        "action",
+        "fee_currency",
+        "gas",
+        "gas_price",
+        "fee",
+        "transaction_hash",
+        "block_number",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.action.clone(),
+            self.fee_currency.to_string(),
+            self.gas.clone(),
+            self.gas_price.clone(),
+            self.fee.clone(),
+            self.transaction_hash
+                .map(|hash| format!("{:?}", hash))
+                .unwrap_or_default(),
+            self.block_number.map(|b| b.to_string()).unwrap_or_default(),
+        ]
+    }
+
+    fn text(&self) -> String {
+        let fee = format!(
+            "fee {} {} ({} gas at {})",
+            self.fee, self.fee_currency, self.gas, self.gas_price
+        );
+        match (self.transaction_hash, self.block_number) {
+            (Some(hash), Some(block)) => {
+                format!(
+                    "{}: transaction {:?} in block {}, {}",
+                    self.action, hash, block, fee
+                )
+            }
+            (Some(hash), None) => format!("{}: transaction {:?}, {}", self.action, hash, fee),
+            _ => format!("{}: estimated {}", self.action, fee),
+        }
+    }
+}
+
+/// Estimates the fees of `tx` and, unless in a dry run, sends it and waits for
+/// its confirmations.
+async fn submit<M: Middleware>(
+    client: &CeloClient<M>,
+    action: String,
+    tx: TransactionRequest,
+    opt: &TransactionOpt,
+    ctx: &Context,
+) -> Result<TransactionRecord> {
+    submit_with_confirmations(client, action, tx, opt, opt.confirmations, ctx).await
+}
+
+async fn submit_with_confirmations<M: Middleware>(
+    client: &CeloClient<M>,
+    action: String,
+    mut tx: TransactionRequest,
+    opt: &TransactionOpt,
+    confirmations: usize,
+    ctx: &Context,
+) -> Result<TransactionRecord> {
+    // The fee currency middleware only fills in the gateway fee when sending,
+    // so fill it in here for the estimate to include it.
+    if let (Some((recipient, fee)), None) = (ctx.gateway_fee, tx.gateway_fee_recipient) {
+        tx.gateway_fee_recipient = Some(recipient);
+        tx.gateway_fee = Some(fee);
+    }
+    let estimate = client.estimate_fee(&tx).await?;
+    let mut record = TransactionRecord {
+        action,
+        fee_currency: ctx.fee_currency.symbol(),
+        gas: estimate.gas.to_string(),
+        gas_price: estimate.gas_price.to_string(),
+        fee: ctx.format_amount(estimate.fee),
+        transaction_hash: None,
+        block_number: None,
+    };
+    if opt.dry_run {
+        return Ok(record);
+    }
+
+    tx.gas = Some(estimate.gas);
+    tx.gas_price = Some(estimate.gas_price);
+    let pending = client.send_transaction(tx).await?;
+    let tx_hash = *pending;
+    record.transaction_hash = Some(tx_hash);
+
+    if confirmations > 0 {
+        eprintln!(
+            "Waiting for {} confirmation(s) of {:?}...",
+            confirmations, tx_hash
+        );
+        let receipt = pending.confirmations(confirmations).await?;
+        if receipt.status == Some(U64::zero()) {
+            return Err(anyhow!("Transaction {:?} reverted", tx_hash));
+        }
+        record.block_number = receipt.block_number.map(|block| block.as_u64());
+    }
+
+    Ok(record)
+}
+
+async fn account_transfer<M: Middleware>(
+    client: &CeloClient<M>,
+    args: AccountTransferOpt,
+    ctx: &Context,
+) -> Result<Vec<TransactionRecord>> {
+    let to = ctx.address(&args.to)?;
+    let (_, metadata) = resolve_token(client, &args.token, ctx).await?;
+    let amount = ctx.parse_units(&args.amount, metadata.decimals)?;
+    let tx = client
+        .transfer_request(&ctx.token_spec(&args.token), to, amount)
+        .await?;
+    let action = format!(
+        "Transfer {} {} to {:?}",
+        ctx.format_units(amount, metadata.decimals),
+        metadata.symbol,
+        to
+    );
+
+    Ok(vec![submit(client, action, tx, &args.tx, ctx).await?])
+}
+
+#[derive(Serialize)]
+struct AllowanceRecord {
+    owner: Address,
+    spender: Address,
+    token: String,
+    allowance: String,
+    /// Block of the spender's last approval, when found by scanning events.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_approval_block: Option<u64>,
+}
+
+impl AllowanceRecord {
+    fn new(
+        owner: Address,
+        spender: Address,
+        token: &TokenMetadata,
+        allowance: U256,
+        ctx: &Context,
+    ) -> Self {
+        // Wallets approve "unlimited" allowances as the maximum value.
+        let allowance = if allowance == U256::max_value() {
+            "max".to_string()
+        } else {
+            ctx.format_units(allowance, token.decimals)
+        };
+        AllowanceRecord {
+            owner,
+            spender,
+            token: token.symbol.clone(),
+            allowance,
+            last_approval_block: None,
+        }
+    }
+}
+
+impl Record for AllowanceRecord {
+    const HEADER: &'static [&'static str] = &[
+        "owner",
+        "spender",
+        "token",
+        "allowance",
+        "last_approval_block",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            format!("{:?}", self.owner),
+            format!("{:?}", self.spender),
+            self.token.clone(),
+            self.allowance.clone(),
+            self.last_approval_block
+                .map(|b| b.to_string())
+                .unwrap_or_default(),
+        ]
+    }
+
+    fn text(&self) -> String {
+        format!(
+            "{:?} may spend {} {} of {:?}",
+            self.spender, self.allowance, self.token, self.owner
+        )
+    }
+}
+
+async fn account_allowance<M: Middleware>(
+    client: &CeloClient<M>,
+    args: AccountAllowanceOpt,
+    ctx: &Context,
+) -> Result<Vec<AllowanceRecord>> {
+    let owner = ctx.address(&args.owner)?;
+    let spender = ctx.address(&args.spender)?;
+    let (token, metadata) = resolve_token(client, &args.token, ctx).await?;
+    let allowance = ctx
+        .at_block(token.allowance(owner, spender))
+        .call()
+        .await
+        .map_err(CelophaneError::from)?;
+
+    Ok(vec![AllowanceRecord::new(
+        owner, spender, &metadata, allowance, ctx,
+    )])
+}
+
+async fn account_approve<M: Middleware>(
+    client: &CeloClient<M>,
+    args: AccountApproveOpt,
+    ctx: &Context,
+) -> Result<Vec<TransactionRecord>> {
+    let spender = ctx.address(&args.spender)?;
+    let (token, metadata) = resolve_token(client, &args.token, ctx).await?;
+    let (amount, amount_text) = if args.amount.eq_ignore_ascii_case("max") {
+        (U256::max_value(), "an unlimited amount of".to_string())
+    } else {
+        let amount = ctx.parse_units(&args.amount, metadata.decimals)?;
+        (amount, ctx.format_units(amount, metadata.decimals))
+    };
+    let tx = token.approve(spender, amount).tx;
+    let action = format!(
+        "Approve {:?} to spend {} {}",
+        spender, amount_text, metadata.symbol
+    );
+
+    Ok(vec![submit(client, action, tx, &args.tx, ctx).await?])
+}
+
+async fn account_revoke<M: Middleware>(
+    client: &CeloClient<M>,
+    args: AccountRevokeOpt,
+    ctx: &Context,
+) -> Result<Vec<TransactionRecord>> {
+    let spender = ctx.address(&args.spender)?;
+    let (token, metadata) = resolve_token(client, &args.token, ctx).await?;
+    let tx = token.approve(spender, U256::zero()).tx;
+    let action = format!("Revoke the {} allowance of {:?}", metadata.symbol, spender);
+
+    Ok(vec![submit(client, action, tx, &args.tx, ctx).await?])
+}
+
+async fn account_allowances<M: Middleware>(
+    client: &CeloClient<M>,
+    args: AccountAllowancesOpt,
+    ctx: &Context,
+) -> Result<Vec<AllowanceRecord>> {
+    let owner = ctx.account_or_default(args.owner.as_ref())?;
+    let to_block = match args.to_block {
+        Some(block) => block,
+        None => client
+            .client()
+            .get_block_number()
+            .await
+            .map_err(CelophaneError::from_node)?
+            .as_u64(),
+    };
+    let all_tokens = args.tokens.is_empty();
+
+    let mut records = Vec::new();
+    for spec in token_specs(args.tokens) {
+        let (erc20, metadata) = match resolve_token(client, &spec, ctx).await {
+            Ok(token) => token,
+            // Not every token is deployed on every network.
+            Err(CelophaneError::RegistryEntryMissing { .. }) if all_tokens => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let approvals =
+            celo::approval_history(&erc20, owner, args.from_block, to_block, args.page_size)
+                .await?;
+
+        // Last approval block of each spender, in order of first approval.
+        let mut spenders: Vec<(Address, U64)> = Vec::new();
+        for approval in approvals {
+            match spenders.iter_mut().find(|(s, _)| *s == approval.spender) {
+                Some((_, block)) => *block = approval.block_number,
+                None => spenders.push((approval.spender, approval.block_number)),
+            }
+        }
+
+        let calls: Vec<_> = spenders
+            .iter()
+            .map(|(spender, _)| erc20.allowance(owner, *spender))
+            .collect();
+        let allowances = client.batch().block(ctx.block).call(calls).await;
+        for ((spender, block), allowance) in spenders.into_iter().zip(allowances) {
+            let allowance = allowance?;
+            if allowance.is_zero() && !args.all {
+                continue;
+            }
+            let mut record = AllowanceRecord::new(owner, spender, &metadata, allowance, ctx);
+            record.last_approval_block = Some(block.as_u64());
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+/// Row of an account's transfer history: the balance before the range, each
+/// transfer with the running balance, and `balanceOf` after the range.
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum HistoryEntry {
+    Opening,
+    Transfer,
+    Closing,
+}
+
+impl HistoryEntry {
+    fn as_str(&self) -> &'static str {
+        match self {
+            HistoryEntry::Opening => "opening",
+            HistoryEntry::Transfer => "transfer",
+            HistoryEntry::Closing => "closing",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TransferHistoryRecord {
+    entry: HistoryEntry,
+    /// Block of the transfer; the first block of the range for the opening
+    /// balance and the last one for the closing balance.
+    block_number: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log_index: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transaction_hash: Option<H256>,
+    token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<Address>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<Address>,
+    /// Signed change of the account's balance. On the closing row, the
+    /// difference between `balanceOf` and the running balance.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    change: Option<String>,
+    /// Running balance on transfer rows, negative if transfers out exceed
+    /// what the transfers in account for.
+    balance: String,
+    /// Whether `balanceOf` matches the running balance, on the closing row.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reconciled: Option<bool>,
+}
+
+impl TransferHistoryRecord {
+    fn balance(entry: HistoryEntry, block_number: u64, token: &str, balance: String) -> Self {
+        TransferHistoryRecord {
+            entry,
+            block_number,
+            log_index: None,
+            transaction_hash: None,
+            token: token.to_string(),
+            from: None,
+            to: None,
+            change: None,
+            balance,
+            reconciled: None,
+        }
+    }
+}
+
+impl Record for TransferHistoryRecord {
+    const HEADER: &'static [&'static str] = &[
+        "entry",
+        "block_number",
+        "log_index",
+        "transaction_hash",
+        "token",
+        "from",
+        "to",
+        "change",
+        "balance",
+        "reconciled",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.entry.as_str().to_string(),
+            self.block_number.to_string(),
+            self.log_index
+                .map(|index| index.to_string())
+                .unwrap_or_default(),
+            self.transaction_hash
+                .map(|hash| format!("{:?}", hash))
+                .unwrap_or_default(),
+            self.token.clone(),
+            self.from
+                .map(|from| format!("{:?}", from))
+                .unwrap_or_default(),
+            self.to.map(|to| format!("{:?}", to)).unwrap_or_default(),
+            self.change.clone().unwrap_or_default(),
+            self.balance.clone(),
+            self.reconciled
+                .map(|reconciled| reconciled.to_string())
+                .unwrap_or_default(),
+        ]
+    }
+
+    fn text(&self) -> String {
+        match self.entry {
+            HistoryEntry::Opening => format!(
+                "block {}: opening balance {} {}",
+                self.block_number, self.balance, self.token
+            ),
+            HistoryEntry::Transfer => format!(
+                "block {}: {} {} ({:?} => {:?}), balance {} (tx {:?})",
+                self.block_number,
+                self.change.as_deref().unwrap_or_default(),
+                self.token,
+                self.from.unwrap_or_default(),
+                self.to.unwrap_or_default(),
+                self.balance,
+                self.transaction_hash.unwrap_or_default()
+            ),
+            HistoryEntry::Closing if self.reconciled == Some(true) => format!(
+                "block {}: closing balance {} {}, reconciles with the transfers",
+                self.block_number, self.balance, self.token
+            ),
+            HistoryEntry::Closing => format!(
+                "block {}: closing balance {} {}, differs from the transfers by {} {}",
+                self.block_number,
+                self.balance,
+                self.token,
+                self.change.as_deref().unwrap_or_default(),
+                self.token
+            ),
+        }
+    }
+}
+
+/// Balance of an account replayed from its transfers, which goes below zero
+/// when balance changes without a `Transfer` event were missed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct RunningBalance {
+    negative: bool,
+    magnitude: U256,
+}
+
+impl RunningBalance {
+    fn new(balance: U256) -> Self {
+        RunningBalance {
+            negative: false,
+            magnitude: balance,
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.magnitude.is_zero()
+    }
+
+    /// The balance plus `value`, `None` on overflow.
+    fn add(self, value: U256) -> Option<Self> {
+        if !self.negative {
+            return Some(RunningBalance::new(self.magnitude.checked_add(value)?));
+        }
+        Some(if self.magnitude > value {
+            RunningBalance {
+                negative: true,
+                magnitude: self.magnitude - value,
+            }
+        } else {
+            RunningBalance::new(value - self.magnitude)
+        })
+    }
+
+    /// The balance minus `value`, `None` on overflow.
+    fn sub(self, value: U256) -> Option<Self> {
+        if self.negative {
+            return Some(RunningBalance {
+                negative: true,
+                magnitude: self.magnitude.checked_add(value)?,
+            });
+        }
+        Some(if self.magnitude >= value {
+            RunningBalance::new(self.magnitude - value)
+        } else {
+            RunningBalance {
+                negative: true,
+                magnitude: value - self.magnitude,
+            }
+        })
+    }
+
+    /// The balance minus `other`, `None` on overflow.
+    fn sub_balance(self, other: RunningBalance) -> Option<Self> {
+        if other.negative {
+            self.add(other.magnitude)
+        } else {
+            self.sub(other.magnitude)
+        }
+    }
+
+    fn format(&self, ctx: &Context, decimals: u8) -> String {
+        let sign = if self.negative { "-" } else { "" };
+        format!("{}{}", sign, ctx.format_units(self.magnitude, decimals))
+    }
+
+    /// Formats the balance as a change, always signed.
+    fn format_change(&self, ctx: &Context, decimals: u8) -> String {
+        let sign = if self.negative { "-" } else { "+" };
+        format!("{}{}", sign, ctx.format_units(self.magnitude, decimals))
+    }
+}
+
+async fn account_history<M: Middleware>(
+    client: &CeloClient<M>,
+    args: AccountHistoryOpt,
+    ctx: &Context,
+) -> Result<Vec<TransferHistoryRecord>> {
+    let address = ctx.account_or_default(args.address.as_ref())?;
+    let (token, metadata) = resolve_token(client, &args.token, ctx).await?;
+    let to_block = match args.to_block {
+        Some(block) => block,
+        None => client
+            .client()
+            .get_block_number()
+            .await
+            .map_err(CelophaneError::from_node)?
+            .as_u64(),
+    };
+
+    // Balances before and after the range, to start the running balance from
+    // and to reconcile it with.
+    let opening_balance = match args.from_block.checked_sub(1) {
+        Some(block) => token
+            .balance_of(address)
+            .block(BlockNumber::from(block))
+            .call()
+            .await
+            .map_err(CelophaneError::from)?,
+        None => U256::zero(),
+    };
+    let closing_balance = token
+        .balance_of(address)
+        .block(BlockNumber::from(to_block))
+        .call()
+        .await
+        .map_err(CelophaneError::from)?;
+
+    let transfers =
+        celo::transfer_history(&token, address, args.from_block, to_block, args.page_size).await?;
+
+    let overflow = || anyhow!("Running balance of {} overflows", metadata.symbol);
+    let mut balance = RunningBalance::new(opening_balance);
+    let mut records = Vec::with_capacity(transfers.len() + 2);
+    records.push(TransferHistoryRecord::balance(
+        HistoryEntry::Opening,
+        args.from_block,
+        &metadata.symbol,
+        ctx.format_units(opening_balance, metadata.decimals),
+    ));
+    for transfer in transfers {
+        let change = if transfer.from == transfer.to {
+            format!("+{}", ctx.format_units(U256::zero(), metadata.decimals))
+        } else if transfer.to == address {
+            balance = balance.add(transfer.value).ok_or_else(overflow)?;
+            format!("+{}", ctx.format_units(transfer.value, metadata.decimals))
+        } else {
+            // Balance changes without a `Transfer` event, such as native
+            // CELO transfers, can take the running balance below zero.
+            balance = balance.sub(transfer.value).ok_or_else(overflow)?;
+            format!("-{}", ctx.format_units(transfer.value, metadata.decimals))
+        };
+        records.push(TransferHistoryRecord {
+            entry: HistoryEntry::Transfer,
+            block_number: transfer.block_number.as_u64(),
+            log_index: Some(transfer.log_index.as_u64()),
+            transaction_hash: Some(transfer.transaction_hash),
+            token: metadata.symbol.clone(),
+            from: Some(transfer.from),
+            to: Some(transfer.to),
+            change: Some(change),
+            balance: balance.format(ctx, metadata.decimals),
+            reconciled: None,
+        });
+    }
+
+    // Fees paid in the token and native CELO transfers emit no `Transfer`
+    // event, so the running balance can drift from `balanceOf`.
+    let drift = RunningBalance::new(closing_balance)
+        .sub_balance(balance)
+        .ok_or_else(overflow)?;
+    let mut closing = TransferHistoryRecord::balance(
+        HistoryEntry::Closing,
+        to_block,
+        &metadata.symbol,
+        ctx.format_units(closing_balance, metadata.decimals),
+    );
+    closing.change = Some(drift.format_change(ctx, metadata.decimals));
+    closing.reconciled = Some(drift.is_zero());
+    records.push(closing);
+
+    Ok(records)
+}
+
+#[derive(Serialize)]
+struct QuoteRecord {
+    sell_token: String,
+    sell_amount: String,
+    buy_token: String,
+    buy_amount: String,
+}
+
+impl Record for QuoteRecord {
+    const HEADER: &'static [&'static str] =
+        &["sell_token", "sell_amount", "buy_token", "buy_amount"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.sell_token.clone(),
+            self.sell_amount.clone(),
+            self.buy_token.clone(),
+            self.buy_amount.clone(),
+        ]
+    }
+
+    fn text(&self) -> String {
+        format!(
+            "{} {} => {} {}",
+            self.sell_amount, self.sell_token, self.buy_amount, self.buy_token
+        )
+    }
+}
+
+/// Exchanges of the pool of `stable`, or of every pool deployed on the network.
+async fn pool_exchanges<'a, M: Middleware>(
+    client: &CeloClient<M>,
+    ctx: &'a Context,
+    stable: Option<&str>,
+) -> Result<Vec<(&'a ExchangePool, celo::Exchange<M>)>> {
+    let pools = match stable {
+        Some(stable) => vec![ctx.pool(stable)?],
+        None => ctx.pools.iter().collect(),
+    };
+    let mut exchanges = Vec::new();
+    for pool in pools {
+        match client.pool_exchange(pool).await {
+            Ok(exchange) => exchanges.push((pool, exchange)),
+            // Not every pool is deployed on every network.
+            Err(CelophaneError::RegistryEntryMissing { .. }) if stable.is_none() => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(exchanges)
+}
+
+async fn exchange_show<M: Middleware>(
+    client: &CeloClient<M>,
+    args: ExchangeShowOpt,
+    ctx: &Context,
+) -> Result<Vec<QuoteRecord>> {
+    let base_qty = match args.amount {
+        Some(amount) => ctx.parse_amount(&amount)?,
+        None => U256::exp10(celo::TOKEN_DECIMALS as usize),
+    };
+
+    let mut records = Vec::new();
+    for (pool, exchange) in pool_exchanges(client, ctx, args.stable.as_deref()).await? {
+        let calls = vec![
+            exchange.get_buy_token_amount(base_qty, true),
+            exchange.get_buy_token_amount(base_qty, false),
+        ];
+        let quotes = client.batch().block(ctx.block).call(calls).await;
+        let quotes = quotes.into_iter().collect::<celophane::Result<Vec<_>>>()?;
+        let (stable_quote_qty, celo_quote_qty) = (quotes[0], quotes[1]);
+
+        records.push(QuoteRecord {
+            sell_token: Token::Celo.symbol().to_string(),
+            sell_amount: ctx.format_amount(base_qty),
+            buy_token: pool.stable.clone(),
+            buy_amount: ctx.format_amount(stable_quote_qty),
+        });
+        records.push(QuoteRecord {
+            sell_token: pool.stable.clone(),
+            sell_amount: ctx.format_amount(base_qty),
+            buy_token: Token::Celo.symbol().to_string(),
+            buy_amount: ctx.format_amount(celo_quote_qty),
+        });
+    }
+    Ok(records)
+}
+
+#[derive(Serialize)]
+struct ExchangeInfoRecord {
+    stable: String,
+    gold_bucket: String,
+    stable_bucket: String,
+    current_gold_bucket: String,
+    current_stable_bucket: String,
+    spread_percent: String,
+    reserve_fraction_percent: String,
+    mid_price: String,
+    sell_gold_price: String,
+    buy_gold_price: String,
+    update_frequency: u64,
+    minimum_reports: u64,
+    last_bucket_update: String,
+    next_bucket_update: String,
+}
+
+impl Record for ExchangeInfoRecord {
+    const HEADER: &'static [&'static str] = &[
+        "stable",
+        "gold_bucket",
+        "stable_bucket",
+        "current_gold_bucket",
+        "current_stable_bucket",
+        "spread_percent",
+        "reserve_fraction_percent",
+        "mid_price",
+        "sell_gold_price",
+        "buy_gold_price",
+        "update_frequency",
+        "minimum_reports",
+        "last_bucket_update",
+        "next_bucket_update",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.stable.clone(),
+            self.gold_bucket.clone(),
+            self.stable_bucket.clone(),
+            self.current_gold_bucket.clone(),
+            self.current_stable_bucket.clone(),
+            self.spread_percent.clone(),
+            self.reserve_fraction_percent.clone(),
+            self.mid_price.clone(),
+            self.sell_gold_price.clone(),
+            self.buy_gold_price.clone(),
+            self.update_frequency.to_string(),
+            self.minimum_reports.to_string(),
+            self.last_bucket_update.clone(),
+            self.next_bucket_update.clone(),
+        ]
+    }
+
+    fn text(&self) -> String {
+        [
+            format!("CELO/{} pool", self.stable),
+            format!(
+                "  buckets: {} CELO / {} {}",
+                self.gold_bucket, self.stable_bucket, self.stable
+            ),
+            format!(
+                "  current buckets: {} CELO / {} {}",
+                self.current_gold_bucket, self.current_stable_bucket, self.stable
+            ),
+            format!("  spread: {}%", self.spread_percent),
+            format!("  reserve fraction: {}%", self.reserve_fraction_percent),
+            format!("  mid price: {} {} per CELO", self.mid_price, self.stable),
+            format!(
+                "  effective price: sell CELO at {}, buy CELO at {} {}",
+                self.sell_gold_price, self.buy_gold_price, self.stable
+            ),
+            format!(
+                "  bucket updates: every {} s with at least {} oracle report(s)",
+                self.update_frequency, self.minimum_reports
+            ),
+            format!("  last bucket update: {}", self.last_bucket_update),
+            format!("  next bucket update: {}", self.next_bucket_update),
+        ]
+        .join("\n")
+    }
+}
+
+/// Formats a fixidity fraction as a decimal number.
+fn format_fraction(value: U256) -> String {
+    TokenAmount::new(value, mento::FIXIDITY_DECIMALS).to_string()
+}
+
+/// Formats a unix timestamp as an RFC 3339 date.
+fn format_timestamp(timestamp: U256) -> String {
+    Utc.timestamp(timestamp.low_u64() as i64, 0).to_rfc3339()
+}
+
+async fn exchange_info<M: Middleware>(
+    client: &CeloClient<M>,
+    args: ExchangeInfoOpt,
+    ctx: &Context,
+) -> Result<Vec<ExchangeInfoRecord>> {
+    let mut records = Vec::new();
+    for (pool, exchange) in pool_exchanges(client, ctx, args.stable.as_deref()).await? {
+        let state = ExchangeState::fetch_at(&exchange, ctx.block).await?;
+        records.push(ExchangeInfoRecord {
+            stable: pool.stable.clone(),
+            gold_bucket: ctx.format_amount(state.gold_bucket),
+            stable_bucket: ctx.format_amount(state.stable_bucket),
+            current_gold_bucket: ctx.format_amount(state.current_gold_bucket),
+            current_stable_bucket: ctx.format_amount(state.current_stable_bucket),
+            spread_percent: format_fraction(state.spread * 100),
+            reserve_fraction_percent: format_fraction(state.reserve_fraction * 100),
+            mid_price: format_fraction(state.mid_price()),
+            sell_gold_price: format_fraction(state.sell_gold_price()),
+            buy_gold_price: format_fraction(state.buy_gold_price()),
+            update_frequency: state.update_frequency.low_u64(),
+            minimum_reports: state.minimum_reports.low_u64(),
+            last_bucket_update: format_timestamp(state.last_bucket_update),
+            next_bucket_update: format_timestamp(state.next_bucket_update()),
+        });
+    }
+    Ok(records)
+}
+
+#[derive(Serialize)]
+struct LadderRecord {
+    stable: String,
+    sell_token: String,
+    sell_amount: String,
+    buy_token: String,
+    buy_amount: String,
+    /// Stable tokens per CELO.
+    price: String,
+    /// Deviation of `price` from the mid price, positive when to the trader's
+    /// disadvantage.
+    price_impact_percent: String,
+}
+
+impl Record for LadderRecord {
+    const HEADER: &'static [&'static str] = &[
+        "stable",
+        "sell_token",
+        "sell_amount",
+        "buy_token",
+        "buy_amount",
+        "price",
+        "price_impact_percent",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.stable.clone(),
+            self.sell_token.clone(),
+            self.sell_amount.clone(),
+            self.buy_token.clone(),
+            self.buy_amount.clone(),
+            self.price.clone(),
+            self.price_impact_percent.clone(),
+        ]
+    }
+
+    fn text(&self) -> String {
+        format!(
+            "{} {} => {} {}: {} {} per CELO, impact {}%",
+            self.sell_amount,
+            self.sell_token,
+            self.buy_amount,
+            self.buy_token,
+            self.price,
+            self.stable,
+            self.price_impact_percent
+        )
+    }
+}
+
+/// Relative deviation of `price` from `mid_price` in percent, positive when
+/// `price` is worse for the trader. Selling CELO is worse at lower prices,
+/// buying CELO at higher ones.
+fn price_impact(price: U256, mid_price: U256, sells_gold: bool) -> String {
+    let (worse, deviation) = if price <= mid_price {
+        (sells_gold, mid_price - price)
+    } else {
+        (!sells_gold, price - mid_price)
+    };
+    let impact = format_fraction(mento::fixidity_ratio(deviation * 100, mid_price));
+    if worse || deviation.is_zero() {
+        impact
+    } else {
+        format!("-{}", impact)
+    }
+}
+
+/// Quotes of selling and buying CELO and selling and buying the stable token,
+/// in that order, for each of `sizes`.
+async fn live_ladder_quotes<M: Middleware>(
+    client: &CeloClient<M>,
+    exchange: &celo::Exchange<M>,
+    sizes: &[U256],
+    ctx: &Context,
+) -> Result<Vec<(U256, U256, U256, U256, U256)>> {
+    let mut calls = Vec::with_capacity(sizes.len() * 4);
+    for &size in sizes {
+        calls.push(exchange.get_buy_token_amount(size, true));
+        calls.push(exchange.get_buy_token_amount(size, false));
+        calls.push(exchange.get_sell_token_amount(size, false));
+        calls.push(exchange.get_sell_token_amount(size, true));
+    }
+    let quotes = client.batch().block(ctx.block).call(calls).await;
+    let quotes = quotes.into_iter().collect::<celophane::Result<Vec<_>>>()?;
+
+    Ok(sizes
+        .iter()
+        .zip(quotes.chunks(4))
+        .map(|(&size, quote)| (size, quote[0], quote[1], quote[2], quote[3]))
+        .collect())
+}
+
+async fn exchange_ladder<M: Middleware>(
+    client: &CeloClient<M>,
+    args: ExchangeLadderOpt,
+    ctx: &Context,
+) -> Result<Vec<LadderRecord>> {
+    let sizes = args
+        .sizes
+        .iter()
+        .map(|size| ctx.parse_amount(size.trim()))
+        .collect::<Result<Vec<_>>>()?;
+    let celo = Token::Celo.symbol();
+
+    let mut records = Vec::new();
+    for (pool, exchange) in pool_exchanges(client, ctx, args.stable.as_deref()).await? {
+        let state = ExchangeState::fetch_at(&exchange, ctx.block).await?;
+        let mid_price = state.mid_price();
+
+        let quotes = if args.local {
+            let model = MentoPool::from(&state);
+            sizes
+                .iter()
+                .map(|&size| {
+                    let quote = (
+                        size,
+                        model.get_buy_token_amount(size, true),
+                        model.get_buy_token_amount(size, false),
+                        model.get_sell_token_amount(size, false),
+                        model.get_sell_token_amount(size, true),
+                    );
+                    match quote {
+                        (size, Some(a), Some(b), Some(c), Some(d)) => Ok((size, a, b, c, d)),
+                        _ => Err(anyhow!(
+                            "The exchange cannot quote {} {}",
+                            ctx.format_amount(size),
+                            pool.stable
+                        )),
+                    }
+                })
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            live_ladder_quotes(client, &exchange, &sizes, ctx).await?
+        };
+
+        for (size, sell_gold, sell_stable, buy_gold, buy_stable) in quotes {
+            // (CELO amount, stable amount, CELO sold) of each trade.
+            let trades = [
+                (size, sell_gold, true),
+                (sell_stable, size, false),
+                (size, buy_gold, false),
+                (buy_stable, size, true),
+            ];
+            for &(gold_amount, stable_amount, sells_gold) in trades.iter() {
+                let price = mento::fixidity_ratio(stable_amount, gold_amount);
+                let (sell_token, sell_amount, buy_token, buy_amount) = if sells_gold {
+                    (celo, gold_amount, pool.stable.as_str(), stable_amount)
+                } else {
+                    (pool.stable.as_str(), stable_amount, celo, gold_amount)
+                };
+                records.push(LadderRecord {
+                    stable: pool.stable.clone(),
+                    sell_token: sell_token.to_string(),
+                    sell_amount: ctx.format_amount(sell_amount),
+                    buy_token: buy_token.to_string(),
+                    buy_amount: ctx.format_amount(buy_amount),
+                    price: format_fraction(price),
+                    price_impact_percent: price_impact(price, mid_price, sells_gold),
+                });
+            }
+        }
+    }
+    Ok(records)
+}
+
+#[derive(Serialize)]
+struct TradeRecord {
+    stable: String,
+    block_number: u64,
+    timestamp: String,
+    transaction_hash: H256,
+    event: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exchanger: Option<Address>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sell_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sell_amount: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    buy_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    buy_amount: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gold_bucket: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stable_bucket: Option<String>,
+    /// Stable tokens per CELO: the effective price of a trade, the mid price
+    /// of a bucket update.
+    price: String,
 }
 
-fn print_balance(maybe_balance: Option<U256>, label: &str) {
-    match maybe_balance {
-        Some(balance) => println!("{}: {}", label, balance),
-        None => (),
+impl TradeRecord {
+    fn new(
+        pool: &ExchangePool,
+        log: &mento::ExchangeLog,
+        timestamp: Option<U256>,
+        ctx: &Context,
+    ) -> Self {
+        let mut record = TradeRecord {
+            stable: pool.stable.clone(),
+            block_number: log.block_number.as_u64(),
+            timestamp: timestamp.map(format_timestamp).unwrap_or_default(),
+            transaction_hash: log.transaction_hash,
+            event: "",
+            exchanger: None,
+            sell_token: None,
+            sell_amount: None,
+            buy_token: None,
+            buy_amount: None,
+            gold_bucket: None,
+            stable_bucket: None,
+            price: String::new(),
+        };
+        match log.event {
+            ExchangeEvent::Exchanged {
+                exchanger,
+                sell_amount,
+                buy_amount,
+                sold_gold,
+            } => {
+                let celo = Token::Celo.symbol().to_string();
+                let (sell_token, buy_token, price) = if sold_gold {
+                    let price = mento::fixidity_ratio(buy_amount, sell_amount);
+                    (celo, pool.stable.clone(), price)
+                } else {
+                    let price = mento::fixidity_ratio(sell_amount, buy_amount);
+                    (pool.stable.clone(), celo, price)
+                };
+                record.event = "Exchanged";
+                record.exchanger = Some(exchanger);
+                record.sell_token = Some(sell_token);
+                record.sell_amount = Some(ctx.format_amount(sell_amount));
+                record.buy_token = Some(buy_token);
+                record.buy_amount = Some(ctx.format_amount(buy_amount));
+                record.price = format_fraction(price);
+            }
+            ExchangeEvent::BucketsUpdated {
+                gold_bucket,
+                stable_bucket,
+            } => {
+                record.event = "BucketsUpdated";
+                record.gold_bucket = Some(ctx.format_amount(gold_bucket));
+                record.stable_bucket = Some(ctx.format_amount(stable_bucket));
+                record.price = format_fraction(mento::fixidity_ratio(stable_bucket, gold_bucket));
+            }
+        }
+        record
     }
 }
 
-async fn get_balance<M: Middleware, Fut>(token_future: Fut, address: Address) -> Option<U256>
-where
-    Fut: Future<Output = Result<celo::Erc20<M>>>,
-{
-    // One would expect a non-existent token to be an Error on maybe_token,
-    // but there is a token and the balance_of call fails instead.
-    match token_future.await {
-        Ok(token) => match token.balance_of(address).call().await {
-            Ok(balance) => Some(balance),
-            // Ignore unexistent token.
-            Err(_) => None,
+impl Record for TradeRecord {
+    const HEADER: &'static [&'static str] = &[
+        "stable",
+        "block_number",
+        "timestamp",
+        "transaction_hash",
+        "event",
+        "exchanger",
+        "sell_token",
+        "sell_amount",
+        "buy_token",
+        "buy_amount",
+        "gold_bucket",
+        "stable_bucket",
+        "price",
+    ];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.stable.clone(),
+            self.block_number.to_string(),
+            self.timestamp.clone(),
+            format!("{:?}", self.transaction_hash),
+            self.event.to_string(),
+            self.exchanger
+                .map(|a| format!("{:?}", a))
+                .unwrap_or_default(),
+            self.sell_token.clone().unwrap_or_default(),
+            self.sell_amount.clone().unwrap_or_default(),
+            self.buy_token.clone().unwrap_or_default(),
+            self.buy_amount.clone().unwrap_or_default(),
+            self.gold_bucket.clone().unwrap_or_default(),
+            self.stable_bucket.clone().unwrap_or_default(),
+            self.price.clone(),
+        ]
+    }
+
+    fn text(&self) -> String {
+        let prefix = format!("block {} ({})", self.block_number, self.timestamp);
+        match (&self.exchanger, &self.gold_bucket) {
+            (Some(exchanger), _) => format!(
+                "{}: {:?} sold {} {} for {} {} at {} {} per CELO (tx {:?})",
+                prefix,
+                exchanger,
+                self.sell_amount.as_deref().unwrap_or_default(),
+                self.sell_token.as_deref().unwrap_or_default(),
+                self.buy_amount.as_deref().unwrap_or_default(),
+                self.buy_token.as_deref().unwrap_or_default(),
+                self.price,
+                self.stable,
+                self.transaction_hash
+            ),
+            _ => format!(
+                "{}: buckets updated to {} CELO / {} {}, mid price {} {} per CELO (tx {:?})",
+                prefix,
+                self.gold_bucket.as_deref().unwrap_or_default(),
+                self.stable_bucket.as_deref().unwrap_or_default(),
+                self.stable,
+                self.price,
+                self.stable,
+                self.transaction_hash
+            ),
+        }
+    }
+}
+
+/// Maximum number of block headers requested at once for their timestamps.
+const MAX_BLOCK_REQUESTS: usize = 16;
+
+/// Reads the events of `exchanges` between `from_block` and `to_block`,
+/// along with the timestamps of their blocks.
+async fn trade_records<M: Middleware>(
+    client: &CeloClient<M>,
+    exchanges: &[(&ExchangePool, celo::Exchange<M>)],
+    from_block: u64,
+    to_block: u64,
+    page_size: u64,
+    ctx: &Context,
+) -> Result<Vec<TradeRecord>> {
+    let mut logs = Vec::new();
+    for (pool, exchange) in exchanges {
+        for log in mento::exchange_logs(exchange, from_block, to_block, page_size).await? {
+            logs.push((*pool, log));
+        }
+    }
+    logs.sort_by_key(|(_, log)| (log.block_number, log.log_index));
+
+    let mut blocks: Vec<U64> = logs.iter().map(|(_, log)| log.block_number).collect();
+    blocks.dedup();
+    let provider = client.client();
+    let headers = stream::iter(&blocks)
+        .map(|&block| provider.get_block(BlockNumber::Number(block)))
+        .buffered(MAX_BLOCK_REQUESTS)
+        .collect::<Vec<_>>()
+        .await;
+    let mut timestamps = HashMap::new();
+    for (block, header) in blocks.into_iter().zip(headers) {
+        if let Some(header) = header.map_err(CelophaneError::from_node)? {
+            timestamps.insert(block, header.timestamp);
+        }
+    }
+
+    Ok(logs
+        .iter()
+        .map(|(pool, log)| {
+            let timestamp = timestamps.get(&log.block_number).copied();
+            TradeRecord::new(pool, log, timestamp, ctx)
+        })
+        .collect())
+}
+
+async fn exchange_trades<M: Middleware>(
+    client: &CeloClient<M>,
+    args: ExchangeTradesOpt,
+    ctx: &Context,
+    output: OutputFormat,
+) -> Result<()> {
+    let exchanges = pool_exchanges(client, ctx, args.stable.as_deref()).await?;
+    let provider = client.client();
+    let latest_block = || async {
+        provider
+            .get_block_number()
+            .await
+            .map(|block| block.as_u64())
+            .map_err(CelophaneError::from_node)
+    };
+
+    let mut from_block = args.from_block;
+    let mut to_block = match args.to_block {
+        Some(block) => block,
+        None => latest_block().await?,
+    };
+    if !args.follow {
+        let records = trade_records(
+            client,
+            &exchanges,
+            from_block,
+            to_block,
+            args.page_size,
+            ctx,
+        )
+        .await?;
+        return print_records(&records, output);
+    }
+
+    let ws = subscription_provider(&ctx.endpoints).await;
+    let mut heads = match &ws {
+        Some(ws) => match ws.subscribe_blocks().await {
+            Ok(heads) => Some(heads),
+            Err(err) => {
+                eprintln!("Warning: polling for new blocks: {}", err);
+                None
+            }
         },
-        // Ignore unexistent token.
-        Err(_) => None,
+        None => None,
+    };
+
+    // Records are printed as each range is read, so that they show up as
+    // soon as their block is mined.
+    let mut stream = RecordStream::new(output);
+    loop {
+        if from_block <= to_block {
+            let records = trade_records(
+                client,
+                &exchanges,
+                from_block,
+                to_block,
+                args.page_size,
+                ctx,
+            )
+            .await?;
+            stream.print(&records)?;
+            from_block = to_block + 1;
+        }
+        match &mut heads {
+            Some(heads) => match heads.next().await {
+                Some(head) => to_block = head.number.map_or(to_block, |block| block.as_u64()),
+                None => return Err(anyhow!("The node closed the new block subscription")),
+            },
+            None => {
+                tokio::time::sleep(Duration::from_secs(args.poll_interval)).await;
+                to_block = latest_block().await?;
+            }
+        }
+    }
+}
+
+/// A connection to the first WebSocket endpoint among `endpoints`, to
+/// subscribe to new blocks through. Subscriptions are not supported by the
+/// other transports.
+async fn subscription_provider(endpoints: &[Url]) -> Option<Provider<Ws>> {
+    let url = endpoints
+        .iter()
+        .find(|url| matches!(url.scheme(), "ws" | "wss"))?;
+    match Ws::connect(url.as_str()).await {
+        Ok(ws) => Some(Provider::new(ws)),
+        Err(err) => {
+            eprintln!("Warning: polling for new blocks: {}: {}", url, err);
+            None
+        }
     }
 }
 
-async fn account_balance<M: Middleware>(client: Arc<M>, args: AccountBalanceOpt) -> Result<()> {
-    let (celo_balance, cusd_balance, ceur_balance) = join!(
-        get_balance(celo::get_celo_token(client.clone()), args.address),
-        get_balance(celo::get_cusd_token(client.clone()), args.address),
-        get_balance(celo::get_ceur_token(client.clone()), args.address)
+/// Basis points in one hundred percent.
+const MAX_BASIS_POINTS: u64 = 10_000;
+
+/// Converts a slippage percentage into basis points.
+fn slippage_basis_points(max_slippage: f64) -> Result<u64> {
+    if !(0.0..=100.0).contains(&max_slippage) {
+        return Err(anyhow!("--max-slippage must be between 0 and 100"));
+    }
+    Ok((max_slippage * 100.0).round() as u64)
+}
+
+/// Side of a trade on an exchange pool.
+struct PoolSide<'a> {
+    pool: &'a ExchangePool,
+    /// Whether this side of the trade is CELO rather than the stable token.
+    is_celo: bool,
+}
+
+impl<'a> PoolSide<'a> {
+    /// Resolves `token`, CELO or a stable token. Trading CELO goes through the
+    /// pool of `stable`, cUSD by default.
+    fn new(ctx: &'a Context, token: &str, stable: Option<&str>) -> Result<Self> {
+        let celo = Token::Celo.symbol();
+        if token.eq_ignore_ascii_case(celo) {
+            let stable = stable.unwrap_or_else(|| Token::Cusd.symbol());
+            return Ok(PoolSide {
+                pool: ctx.pool(stable)?,
+                is_celo: true,
+            });
+        }
+
+        let pool = ctx.pool(token)?;
+        if let Some(stable) = stable {
+            if !pool.stable.eq_ignore_ascii_case(stable) {
+                return Err(anyhow!("Cannot trade {} in the {} pool", token, stable));
+            }
+        }
+        Ok(PoolSide {
+            pool,
+            is_celo: false,
+        })
+    }
+
+    fn symbol(&self) -> &str {
+        if self.is_celo {
+            Token::Celo.symbol()
+        } else {
+            &self.pool.stable
+        }
+    }
+
+    /// Symbol of the other side of the trade.
+    fn counterpart(&self) -> &str {
+        if self.is_celo {
+            &self.pool.stable
+        } else {
+            Token::Celo.symbol()
+        }
+    }
+
+    async fn token<M: Middleware>(&self, client: &CeloClient<M>) -> Result<celo::Erc20<M>> {
+        if self.is_celo {
+            Ok(client.celo_token().await?)
+        } else {
+            Ok(client.pool_stable_token(self.pool).await?)
+        }
+    }
+}
+
+/// Approves the exchange to spend `amount` of `token` if its current allowance
+/// falls short, waiting for the approval to be mined before the trade.
+async fn approve_exchange<M: Middleware>(
+    client: &CeloClient<M>,
+    exchange: &celo::Exchange<M>,
+    token: &celo::Erc20<M>,
+    symbol: &str,
+    amount: U256,
+    opt: &TransactionOpt,
+    ctx: &Context,
+) -> Result<Option<TransactionRecord>> {
+    let owner = ctx
+        .sender
+        .ok_or_else(|| anyhow!("No account to send transactions from"))?;
+    let allowance = token
+        .allowance(owner, exchange.address())
+        .call()
+        .await
+        .map_err(CelophaneError::from)?;
+    if allowance >= amount {
+        return Ok(None);
+    }
+
+    let action = format!(
+        "Approve {} {} for the exchange",
+        ctx.format_amount(amount),
+        symbol
     );
+    let tx = token.approve(exchange.address(), amount).tx;
+    let record =
+        submit_with_confirmations(client, action, tx, opt, opt.confirmations.max(1), ctx).await?;
+    Ok(Some(record))
+}
 
-    println!("All balances expressed in units of 10^-18.");
-    print_balance(celo_balance, "CELO");
-    print_balance(cusd_balance, "cUSD");
-    print_balance(ceur_balance, "cEUR");
+async fn exchange_sell<M: Middleware>(
+    client: &CeloClient<M>,
+    args: ExchangeSellOpt,
+    ctx: &Context,
+) -> Result<Vec<TransactionRecord>> {
+    let side = PoolSide::new(ctx, &args.from, args.stable.as_deref())?;
+    let basis_points = slippage_basis_points(args.max_slippage)?;
+    let sell_amount = ctx.parse_amount(&args.amount)?;
 
-    Ok(())
+    let exchange = client.pool_exchange(side.pool).await?;
+    let quote = exchange
+        .get_buy_token_amount(sell_amount, side.is_celo)
+        .call()
+        .await
+        .map_err(CelophaneError::from)?;
+    let min_buy_amount = quote * (MAX_BASIS_POINTS - basis_points) / MAX_BASIS_POINTS;
+
+    let mut records = Vec::new();
+    let token = side.token(client).await?;
+    if let Some(approval) = approve_exchange(
+        client,
+        &exchange,
+        &token,
+        side.symbol(),
+        sell_amount,
+        &args.tx,
+        ctx,
+    )
+    .await?
+    {
+        records.push(approval);
+        if args.tx.dry_run {
+            eprintln!("The trade can only be estimated once the approval is mined");
+            return Ok(records);
+        }
+    }
+
+    let action = format!(
+        "Sell {} {} for at least {} {} (quoted {} {})",
+        ctx.format_amount(sell_amount),
+        side.symbol(),
+        ctx.format_amount(min_buy_amount),
+        side.counterpart(),
+        ctx.format_amount(quote),
+        side.counterpart()
+    );
+    let tx = exchange.sell(sell_amount, min_buy_amount, side.is_celo).tx;
+    records.push(submit(client, action, tx, &args.tx, ctx).await?);
+    Ok(records)
 }
 
-async fn exchange_show<M: Middleware>(client: Arc<M>, args: ExchangeShowOpt) -> Result<()> {
-    let exchange = celo::get_exchange(client.clone()).await?;
+async fn exchange_buy<M: Middleware>(
+    client: &CeloClient<M>,
+    args: ExchangeBuyOpt,
+    ctx: &Context,
+) -> Result<Vec<TransactionRecord>> {
+    let side = PoolSide::new(ctx, &args.to, args.stable.as_deref())?;
+    let basis_points = slippage_basis_points(args.max_slippage)?;
+    let buy_amount = ctx.parse_amount(&args.amount)?;
 
-    let base_qty = args.amount;
+    let exchange = client.pool_exchange(side.pool).await?;
+    let quote = exchange
+        .get_sell_token_amount(buy_amount, !side.is_celo)
+        .call()
+        .await
+        .map_err(CelophaneError::from)?;
+    let max_sell_amount = quote * (MAX_BASIS_POINTS + basis_points) / MAX_BASIS_POINTS;
 
-    let cusd_call = exchange.get_buy_token_amount(base_qty, true);
-    let celo_call = exchange.get_buy_token_amount(base_qty, false);
+    let mut records = Vec::new();
+    let sell_side = PoolSide {
+        pool: side.pool,
+        is_celo: !side.is_celo,
+    };
+    let token = sell_side.token(client).await?;
+    if let Some(approval) = approve_exchange(
+        client,
+        &exchange,
+        &token,
+        sell_side.symbol(),
+        max_sell_amount,
+        &args.tx,
+        ctx,
+    )
+    .await?
+    {
+        records.push(approval);
+        if args.tx.dry_run {
+            eprintln!("The trade can only be estimated once the approval is mined");
+            return Ok(records);
+        }
+    }
 
-    let (cusd_quote_qty, celo_quote_qty) = join!(cusd_call.call(), celo_call.call());
+    let action = format!(
+        "Buy {} {} for at most {} {} (quoted {} {})",
+        ctx.format_amount(buy_amount),
+        side.symbol(),
+        ctx.format_amount(max_sell_amount),
+        side.counterpart(),
+        ctx.format_amount(quote),
+        side.counterpart()
+    );
+    let tx = exchange.buy(buy_amount, max_sell_amount, side.is_celo).tx;
+    records.push(submit(client, action, tx, &args.tx, ctx).await?);
+    Ok(records)
+}
 
-    println!("{} CELO => {} cUSD", base_qty, cusd_quote_qty.unwrap());
-    println!("{} cUSD => {} CELO", base_qty, celo_quote_qty.unwrap());
+#[derive(Serialize)]
+struct RegistryEntryRecord {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<Address>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
 
-    Ok(())
+impl RegistryEntryRecord {
+    fn new(name: &str, address: celophane::Result<Address>) -> Self {
+        let (address, error) = match address {
+            Ok(address) => (Some(address), None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+        RegistryEntryRecord {
+            name: name.to_string(),
+            address,
+            error,
+        }
+    }
 }
 
-async fn run_command<M: Middleware>(provider: M, args: CelophaneOpt) -> Result<()> {
-    let client = Arc::new(provider);
+impl Record for RegistryEntryRecord {
+    const HEADER: &'static [&'static str] = &["name", "address", "error"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.address.map(|a| format!("{:?}", a)).unwrap_or_default(),
+            self.error.clone().unwrap_or_default(),
+        ]
+    }
+
+    fn text(&self) -> String {
+        match (&self.address, &self.error) {
+            (Some(address), _) => format!("{}: {:?}", self.name, address),
+            (None, error) => format!("{}: {}", self.name, error.as_deref().unwrap_or_default()),
+        }
+    }
+}
+
+async fn registry_list<M: Middleware>(client: &CeloClient<M>) -> Result<Vec<RegistryEntryRecord>> {
+    let addresses = client.registry_lookup_all(registry::CORE_CONTRACTS).await?;
+
+    Ok(registry::CORE_CONTRACTS
+        .iter()
+        .zip(addresses)
+        .map(|(name, address)| RegistryEntryRecord::new(name, address))
+        .collect())
+}
+
+async fn registry_get<M: Middleware>(
+    client: &CeloClient<M>,
+    args: RegistryGetOpt,
+) -> Result<Vec<RegistryEntryRecord>> {
+    let address = client.registry_lookup(&args.name).await?;
+
+    Ok(vec![RegistryEntryRecord::new(&args.name, Ok(address))])
+}
+
+#[derive(Serialize)]
+struct RegistryUpdateRecord {
+    block_number: u64,
+    identifier: String,
+    address: Address,
+    transaction_hash: H256,
+}
+
+impl Record for RegistryUpdateRecord {
+    const HEADER: &'static [&'static str] =
+        &["block_number", "identifier", "address", "transaction_hash"];
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.block_number.to_string(),
+            self.identifier.clone(),
+            format!("{:?}", self.address),
+            format!("{:?}", self.transaction_hash),
+        ]
+    }
+
+    fn text(&self) -> String {
+        format!(
+            "block {}: {} => {:?} (tx {:?})",
+            self.block_number, self.identifier, self.address, self.transaction_hash
+        )
+    }
+}
+
+async fn registry_history<M: Middleware>(
+    client: &CeloClient<M>,
+    args: RegistryHistoryOpt,
+) -> Result<Vec<RegistryUpdateRecord>> {
+    let to_block = match args.to_block {
+        Some(block) => block,
+        None => client
+            .client()
+            .get_block_number()
+            .await
+            .map_err(CelophaneError::from_node)?
+            .as_u64(),
+    };
+    let updates = celo::registry_history(
+        &client.registry(),
+        args.name.as_deref(),
+        args.from_block,
+        to_block,
+        args.page_size,
+    )
+    .await?;
+
+    Ok(updates
+        .into_iter()
+        .map(|update| RegistryUpdateRecord {
+            block_number: update.block_number.as_u64(),
+            identifier: update.identifier,
+            address: update.address,
+            transaction_hash: update.transaction_hash,
+        })
+        .collect())
+}
+
+async fn run_command<M: Middleware>(
+    provider: M,
+    batch_client: Arc<dyn BatchClient>,
+    args: CelophaneOpt,
+    network: Network,
+    mut ctx: Context,
+) -> Result<()> {
+    if args.cmd.reads_block_range() && (args.block.is_some() || args.at.is_some()) {
+        return Err(anyhow!(
+            "--block and --at do not apply to range commands, use --from-block and --to-block"
+        ));
+    }
+    let tx = match args.cmd.transaction() {
+        Some(tx) => tx,
+        None => {
+            let mut client = connect(provider, batch_client, &args, &network)?;
+            ctx.block = resolve_block(&client, &args).await?;
+            if let (Some(block), None) = (ctx.block, args.registry_block) {
+                client = client.with_registry_block(block);
+            }
+            return execute(client, args, ctx).await;
+        }
+    };
+    if args.block.is_some() || args.at.is_some() {
+        return Err(anyhow!("--block and --at only apply to read commands"));
+    }
+
+    let chain_id = provider
+        .get_chainid()
+        .await
+        .map_err(CelophaneError::from_node)?;
+    let wallet = tx.signer.wallet()?.set_chain_id(chain_id.as_u64());
+    let mut middleware =
+        FeeCurrencyMiddleware::new(SignerMiddleware::new(provider, wallet.clone()))
+            .with_sender(wallet.address());
+    if let (Some(recipient), Some(fee)) = (tx.gateway_fee_recipient, &tx.gateway_fee) {
+        let fee = ctx.parse_amount(fee)?;
+        middleware = middleware.with_gateway_fee(recipient, fee);
+        ctx.gateway_fee = Some((recipient, fee));
+    }
+
+    let client = connect(middleware, batch_client, &args, &network)?;
+    let fee_currency = client.fee_currency(ctx.fee_currency).await?;
+    client.client().set_fee_currency(fee_currency);
+    ctx.sender = Some(wallet.address());
+
+    execute(client, args, ctx).await
+}
+
+/// Resolves `--block` or `--at` to the block read commands run against.
+async fn resolve_block<M: Middleware>(
+    client: &CeloClient<M>,
+    args: &CelophaneOpt,
+) -> Result<Option<BlockNumber>> {
+    if let Some(at) = args.at {
+        let timestamp = U256::from(at.timestamp().max(0) as u64);
+        let block = client.block_at_timestamp(timestamp).await?;
+        return Ok(Some(BlockNumber::Number(block)));
+    }
+    let block = match args.block {
+        Some(BlockSpec::Number(block)) => Some(BlockNumber::from(block)),
+        // eth_call only takes block numbers and tags.
+        Some(BlockSpec::Hash(hash)) => {
+            let block = client
+                .client()
+                .get_block(hash)
+                .await
+                .map_err(CelophaneError::from_node)?
+                .ok_or_else(|| anyhow!("Block {:?} not found", hash))?;
+            let number = block
+                .number
+                .ok_or_else(|| anyhow!("Block {:?} is pending", hash))?;
+            Some(BlockNumber::Number(number))
+        }
+        Some(BlockSpec::Latest) | None => None,
+        Some(BlockSpec::Pending) => Some(BlockNumber::Pending),
+    };
+    Ok(block)
+}
+
+/// Wraps `provider` into a client of `network`, using the registry cache and
+/// Multicall if requested.
+fn connect<M: Middleware>(
+    provider: M,
+    batch_client: Arc<dyn BatchClient>,
+    args: &CelophaneOpt,
+    network: &Network,
+) -> Result<CeloClient<M>> {
+    let mut client = CeloClient::from(provider)
+        .with_registry_address(network.registry)
+        .with_batch_client(batch_client);
+    if let Some(path) = &args.registry_cache {
+        let cache =
+            RegistryCache::load(path)?.with_max_age(Duration::from_secs(args.registry_max_age));
+        client = client.with_registry_cache(cache, args.registry_block.map(U64::from));
+    }
+    if let Some(address) = args.multicall.or(network.multicall) {
+        client = client.with_multicall(address);
+    }
+    Ok(client)
+}
+
+async fn execute<M: Middleware>(
+    client: CeloClient<M>,
+    args: CelophaneOpt,
+    ctx: Context,
+) -> Result<()> {
+    let output = ctx.output;
     match args.cmd {
         Command::Account(opt) => match opt {
-            AccountCommand::Balance(opt) => account_balance(client, opt).await?,
+            AccountCommand::Balance(opt) => {
+                print_records(&account_balance(&client, opt, &ctx).await?, output)?
+            }
+            AccountCommand::Transfer(opt) => {
+                print_records(&account_transfer(&client, opt, &ctx).await?, output)?
+            }
+            AccountCommand::History(opt) => {
+                print_records(&account_history(&client, opt, &ctx).await?, output)?
+            }
+            AccountCommand::Allowance(opt) => {
+                print_records(&account_allowance(&client, opt, &ctx).await?, output)?
+            }
+            AccountCommand::Approve(opt) => {
+                print_records(&account_approve(&client, opt, &ctx).await?, output)?
+            }
+            AccountCommand::Revoke(opt) => {
+                print_records(&account_revoke(&client, opt, &ctx).await?, output)?
+            }
+            AccountCommand::Allowances(opt) => {
+                print_records(&account_allowances(&client, opt, &ctx).await?, output)?
+            }
         },
         Command::Exchange(opt) => match opt {
-            ExchangeCommand::Show(opt) => exchange_show(client, opt).await?,
+            ExchangeCommand::Show(opt) => {
+                print_records(&exchange_show(&client, opt, &ctx).await?, output)?
+            }
+            ExchangeCommand::Info(opt) => {
+                print_records(&exchange_info(&client, opt, &ctx).await?, output)?
+            }
+            ExchangeCommand::Ladder(opt) => {
+                print_records(&exchange_ladder(&client, opt, &ctx).await?, output)?
+            }
+            ExchangeCommand::Trades(opt) => exchange_trades(&client, opt, &ctx, output).await?,
+            ExchangeCommand::Sell(opt) => {
+                print_records(&exchange_sell(&client, opt, &ctx).await?, output)?
+            }
+            ExchangeCommand::Buy(opt) => {
+                print_records(&exchange_buy(&client, opt, &ctx).await?, output)?
+            }
+        },
+        Command::Registry(opt) => match opt {
+            RegistryCommand::List => print_records(&registry_list(&client).await?, output)?,
+            RegistryCommand::Get(opt) => print_records(&registry_get(&client, opt).await?, output)?,
+            RegistryCommand::History(opt) => {
+                print_records(&registry_history(&client, opt).await?, output)?
+            }
         },
     }
 
+    if let Some(path) = &args.registry_cache {
+        if let Some(cache) = client.registry_cache().await {
+            cache.save(path)?;
+        }
+    }
+
     Ok(())
 }
 
-enum EitherProvider {
-    Http(Provider<Http>),
-    Ws(Provider<Ws>),
+/// Connects to `url`, checking that it serves `network`.
+async fn connect_endpoint(url: &Url, network: &Network) -> celophane::Result<Transport> {
+    let transport = Transport::connect(url).await?;
+    network.verify(&Provider::new(&transport)).await?;
+    Ok(transport)
 }
 
-async fn create_provider(url: &Url) -> Result<EitherProvider> {
-    let provider = match url.scheme() {
-        "https" | "http" => EitherProvider::Http(Provider::<Http>::try_from(url.as_str())?),
-        "wss" | "ws" => EitherProvider::Ws(Provider::<Ws>::connect(url.as_str()).await?),
-        scheme => Err(anyhow!("Unknown URL scheme \"{}\"", scheme))?,
-    };
-    Ok(provider)
+/// Connects to `urls`, checking that each serves `network`. Endpoints that
+/// cannot be reached are skipped as long as another one can be.
+async fn create_provider(
+    urls: &[Url],
+    network: &Network,
+    args: &CelophaneOpt,
+) -> Result<Provider<ThrottledClient<FailoverClient>>> {
+    let timeout = Some(args.timeout)
+        .filter(|&secs| secs > 0)
+        .map(Duration::from_secs);
+    let mut endpoints = Vec::with_capacity(urls.len());
+    for url in urls {
+        let connection = connect_endpoint(url, network);
+        let connection = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connection)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(CelophaneError::Transport(format!(
+                        "timed out after {:?}",
+                        timeout
+                    )))
+                }),
+            None => connection.await,
+        };
+        match connection {
+            Ok(transport) => endpoints.push(Endpoint::new(url.clone(), transport)),
+            // An endpoint on the wrong chain is a configuration mistake.
+            Err(err @ CelophaneError::WrongChain { .. }) => {
+                return Err(anyhow!("{}: {}", url, err))
+            }
+            Err(err) if urls.len() > 1 => eprintln!("Warning: skipping {}: {}", url, err),
+            Err(err) => return Err(anyhow!("{}: {}", url, err)),
+        }
+    }
+    if endpoints.is_empty() {
+        return Err(anyhow!("No endpoint could be reached"));
+    }
+
+    let client = FailoverClient::new(endpoints).with_verbose(args.verbose);
+    let client = ThrottledClient::new(client)
+        .with_timeout(timeout)
+        .with_max_concurrency(args.max_concurrency)
+        .with_requests_per_second(args.requests_per_second);
+    Ok(Provider::new(client))
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = CelophaneOpt::from_args();
+    let config = Config::load(args.config.as_deref())?;
+    let profile = config.profile(args.profile.as_deref())?;
+    let network = args.network.as_ref().or(profile.network.as_ref());
+    let network = config.network(network.map_or("local", String::as_str))?;
+    let ctx = Context::new(&args, &config, &profile, &network)?;
+    let provider = create_provider(&ctx.endpoints, &network, &args).await?;
+    // Batches go through the same endpoints and limits as other requests.
+    let batch_client = Arc::new(provider.as_ref().clone());
 
-    let provider = create_provider(&args.endpoint).await?;
-    let result = match provider {
-        EitherProvider::Http(p) => run_command(p, args).await,
-        EitherProvider::Ws(p) => run_command(p, args).await,
-    };
+    run_command(provider, batch_client, args, network, ctx).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negative(magnitude: u64) -> RunningBalance {
+        RunningBalance {
+            negative: true,
+            magnitude: U256::from(magnitude),
+        }
+    }
+
+    #[test]
+    fn running_balance_goes_below_zero() {
+        let balance = RunningBalance::new(U256::from(3));
+        assert_eq!(balance.sub(U256::from(5)), Some(negative(2)));
+        assert_eq!(negative(2).sub(U256::from(1)), Some(negative(3)));
+        assert_eq!(
+            negative(2).add(U256::from(2)),
+            Some(RunningBalance::new(U256::zero()))
+        );
+        assert_eq!(
+            negative(2).add(U256::from(7)),
+            Some(RunningBalance::new(U256::from(5)))
+        );
+        assert_eq!(negative(5).add(U256::from(2)), Some(negative(3)));
+    }
+
+    #[test]
+    fn running_balance_drift() {
+        let closing = RunningBalance::new(U256::from(4));
+        assert_eq!(
+            closing.sub_balance(negative(2)),
+            Some(RunningBalance::new(U256::from(6)))
+        );
+        assert_eq!(
+            closing.sub_balance(RunningBalance::new(U256::from(9))),
+            Some(negative(5))
+        );
+    }
 
-    result
+    #[test]
+    fn running_balance_overflow_is_reported() {
+        let balance = RunningBalance::new(U256::MAX);
+        assert_eq!(balance.add(U256::one()), None);
+        assert_eq!(negative(1).sub(U256::MAX), None);
+    }
+
+    #[test]
+    fn explicit_network_uses_its_own_endpoint() {
+        let config = Config::default();
+        let profile = Profile {
+            network: Some("alfajores".to_string()),
+            endpoint: Some("http://localhost:9000".parse().unwrap()),
+            ..Profile::default()
+        };
+        let endpoints = |argv: &[&str]| {
+            let args = CelophaneOpt::from_iter(argv);
+            let network = args.network.as_ref().or(profile.network.as_ref()).unwrap();
+            let network = config.network(network).unwrap();
+            Context::new(&args, &config, &profile, &network)
+                .unwrap()
+                .endpoints
+        };
+
+        let profile_endpoint = profile.endpoint.clone().unwrap();
+        let mainnet = config.network("mainnet").unwrap().endpoint;
+        assert_eq!(
+            endpoints(&["celophane", "registry", "list"]),
+            vec![profile_endpoint]
+        );
+        assert_eq!(
+            endpoints(&["celophane", "--network", "mainnet", "registry", "list"]),
+            vec![mainnet]
+        );
+    }
 }
@@ -0,0 +1,513 @@
+use crate::celo::{block_pages, BucketsUpdatedFilter, Exchange, ExchangedFilter};
+use crate::error::{CelophaneError, Result};
+use ethers::contract::builders::ContractCall;
+use ethers::providers::Middleware;
+use ethers::types::{Address, BlockNumber, Log, H256, U256, U64};
+use tokio::try_join;
+
+/// Decimals of the fixed-point fractions (FixidityLib) used by the exchange.
+pub const FIXIDITY_DECIMALS: u8 = 24;
+
+/// Parameters and buckets of a Mento exchange, read from its contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExchangeState {
+    /// CELO bucket as stored in the contract.
+    pub gold_bucket: U256,
+    /// Stable token bucket as stored in the contract.
+    pub stable_bucket: U256,
+    /// CELO bucket quotes are computed from, which differs from `gold_bucket`
+    /// when the buckets are due to be reset on the next trade.
+    pub current_gold_bucket: U256,
+    /// Stable token bucket quotes are computed from.
+    pub current_stable_bucket: U256,
+    /// Fee charged on trades, as a fixidity fraction.
+    pub spread: U256,
+    /// Fraction of the reserve's CELO put in the CELO bucket on a reset, as a
+    /// fixidity fraction.
+    pub reserve_fraction: U256,
+    /// Minimum number of seconds between bucket resets.
+    pub update_frequency: U256,
+    /// Timestamp of the last bucket reset.
+    pub last_bucket_update: U256,
+    /// Number of oracle reports required for the buckets to be reset.
+    pub minimum_reports: U256,
+}
+
+impl ExchangeState {
+    /// Reads the state of `exchange` in parallel.
+    pub async fn fetch<M: Middleware>(exchange: &Exchange<M>) -> Result<Self> {
+        Self::fetch_at(exchange, None).await
+    }
+
+    /// Like [`ExchangeState::fetch`], but against the state at `block`.
+    pub async fn fetch_at<M: Middleware>(
+        exchange: &Exchange<M>,
+        block: Option<BlockNumber>,
+    ) -> Result<Self> {
+        let at = |call: ContractCall<M, U256>| match block {
+            Some(block) => call.block(block),
+            None => call,
+        };
+        let gold_bucket = at(exchange.gold_bucket());
+        let stable_bucket = at(exchange.stable_bucket());
+        let mut buckets = exchange.get_buy_and_sell_buckets(true);
+        if let Some(block) = block {
+            buckets = buckets.block(block);
+        }
+        let spread = at(exchange.spread());
+        let reserve_fraction = at(exchange.reserve_fraction());
+        let update_frequency = at(exchange.update_frequency());
+        let last_bucket_update = at(exchange.last_bucket_update());
+        let minimum_reports = at(exchange.minimum_reports());
+
+        let (
+            gold_bucket,
+            stable_bucket,
+            (current_stable_bucket, current_gold_bucket),
+            spread,
+            reserve_fraction,
+            update_frequency,
+            last_bucket_update,
+            minimum_reports,
+        ) = try_join!(
+            gold_bucket.call(),
+            stable_bucket.call(),
+            buckets.call(),
+            spread.call(),
+            reserve_fraction.call(),
+            update_frequency.call(),
+            last_bucket_update.call(),
+            minimum_reports.call()
+        )
+        .map_err(CelophaneError::from)?;
+
+        Ok(ExchangeState {
+            gold_bucket,
+            stable_bucket,
+            current_gold_bucket,
+            current_stable_bucket,
+            spread,
+            reserve_fraction,
+            update_frequency,
+            last_bucket_update,
+            minimum_reports,
+        })
+    }
+
+    /// Earliest timestamp the buckets can be reset at, provided enough fresh
+    /// oracle reports.
+    pub fn next_bucket_update(&self) -> U256 {
+        self.last_bucket_update + self.update_frequency
+    }
+
+    /// Stable tokens per CELO implied by the current buckets, as a fixidity
+    /// fraction.
+    pub fn mid_price(&self) -> U256 {
+        fixidity_ratio(self.current_stable_bucket, self.current_gold_bucket)
+    }
+
+    /// Stable tokens received per CELO sold, after the spread, as a fixidity
+    /// fraction. Ignores the price impact of the trade.
+    pub fn sell_gold_price(&self) -> U256 {
+        self.mid_price() * (fixed1() - self.spread) / fixed1()
+    }
+
+    /// Stable tokens paid per CELO bought, after the spread, as a fixidity
+    /// fraction. Ignores the price impact of the trade.
+    pub fn buy_gold_price(&self) -> U256 {
+        if self.spread >= fixed1() {
+            return U256::zero();
+        }
+        self.mid_price() * fixed1() / (fixed1() - self.spread)
+    }
+}
+
+/// Offline model of a Mento exchange, reproducing the contract's quotes
+/// (`getBuyTokenAmount` and `getSellTokenAmount`) bit for bit, fixidity
+/// rounding included.
+///
+/// Quotes are `None` wherever the contract would revert, e.g. when buying more
+/// than the bucket holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MentoPool {
+    pub gold_bucket: U256,
+    pub stable_bucket: U256,
+    /// Fee charged on trades, as a fixidity fraction.
+    pub spread: U256,
+}
+
+impl MentoPool {
+    pub fn new(gold_bucket: U256, stable_bucket: U256, spread: U256) -> Self {
+        MentoPool {
+            gold_bucket,
+            stable_bucket,
+            spread,
+        }
+    }
+
+    /// Amount of the bought token received for `sell_amount`, as
+    /// `getBuyTokenAmount`.
+    pub fn get_buy_token_amount(&self, sell_amount: U256, sell_gold: bool) -> Option<U256> {
+        if sell_amount.is_zero() {
+            return Some(U256::zero());
+        }
+        let (buy_bucket, sell_bucket) = self.buckets(sell_gold);
+        let reduced_sell_amount =
+            fixidity_multiply(fixed1().checked_sub(self.spread)?, new_fixed(sell_amount)?)?;
+        let numerator = fixidity_multiply(reduced_sell_amount, new_fixed(buy_bucket)?)?;
+        let denominator = new_fixed(sell_bucket)?.checked_add(reduced_sell_amount)?;
+        numerator.checked_div(denominator)
+    }
+
+    /// Amount of the sold token needed to receive `buy_amount`, as
+    /// `getSellTokenAmount`.
+    pub fn get_sell_token_amount(&self, buy_amount: U256, sell_gold: bool) -> Option<U256> {
+        if buy_amount.is_zero() {
+            return Some(U256::zero());
+        }
+        let (buy_bucket, sell_bucket) = self.buckets(sell_gold);
+        let numerator = new_fixed(buy_amount.checked_mul(sell_bucket)?)?;
+        let denominator = fixidity_multiply(
+            new_fixed(buy_bucket.checked_sub(buy_amount)?)?,
+            fixed1().checked_sub(self.spread)?,
+        )?;
+        numerator.checked_div(denominator)
+    }
+
+    /// The buckets of the bought and the sold token.
+    fn buckets(&self, sell_gold: bool) -> (U256, U256) {
+        if sell_gold {
+            (self.stable_bucket, self.gold_bucket)
+        } else {
+            (self.gold_bucket, self.stable_bucket)
+        }
+    }
+}
+
+impl From<&ExchangeState> for MentoPool {
+    /// Models the exchange with the buckets quotes are currently computed from.
+    fn from(state: &ExchangeState) -> Self {
+        MentoPool::new(
+            state.current_gold_bucket,
+            state.current_stable_bucket,
+            state.spread,
+        )
+    }
+}
+
+/// Precision `FixidityLib.multiply` truncates the fractional parts to.
+fn mul_precision() -> U256 {
+    U256::exp10(12)
+}
+
+/// `FixidityLib.newFixed`.
+fn new_fixed(x: U256) -> Option<U256> {
+    x.checked_mul(fixed1())
+}
+
+/// `FixidityLib.multiply`, which multiplies the integer and fractional parts
+/// separately and truncates the product of the fractional parts.
+fn fixidity_multiply(x: U256, y: U256) -> Option<U256> {
+    let (x1, x2) = x.div_mod(fixed1());
+    let (y1, y2) = y.div_mod(fixed1());
+
+    let x1y1 = new_fixed(x1.checked_mul(y1)?)?;
+    let x2y1 = x2.checked_mul(y1)?;
+    let x1y2 = x1.checked_mul(y2)?;
+    let x2y2 = (x2 / mul_precision()).checked_mul(y2 / mul_precision())?;
+
+    x1y1.checked_add(x2y1)?.checked_add(x1y2)?.checked_add(x2y2)
+}
+
+/// One as a fixidity fraction.
+pub fn fixed1() -> U256 {
+    U256::exp10(FIXIDITY_DECIMALS as usize)
+}
+
+/// `numerator / denominator` as a fixidity fraction, zero if the denominator is.
+pub fn fixidity_ratio(numerator: U256, denominator: U256) -> U256 {
+    if denominator.is_zero() {
+        return U256::zero();
+    }
+    numerator * fixed1() / denominator
+}
+
+/// An event of a Mento exchange.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExchangeEvent {
+    /// A trade.
+    Exchanged {
+        exchanger: Address,
+        sell_amount: U256,
+        buy_amount: U256,
+        sold_gold: bool,
+    },
+    /// A bucket reset, triggered by the first trade after the update frequency.
+    BucketsUpdated {
+        gold_bucket: U256,
+        stable_bucket: U256,
+    },
+}
+
+/// An exchange event along with where it was emitted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExchangeLog {
+    pub event: ExchangeEvent,
+    pub block_number: U64,
+    /// Position of the event in its block.
+    pub log_index: U256,
+    pub transaction_hash: H256,
+}
+
+/// Reads the `Exchanged` and `BucketsUpdated` events of `exchange` between
+/// `from_block` and `to_block` (inclusive), querying at most `page_size`
+/// blocks at a time to stay within the node's log limits.
+///
+/// Events are sorted in the order they were emitted in, so bucket resets come
+/// before the trade that triggers them.
+pub async fn exchange_logs<M: Middleware>(
+    exchange: &Exchange<M>,
+    from_block: u64,
+    to_block: u64,
+    page_size: u64,
+) -> Result<Vec<ExchangeLog>> {
+    let client = exchange.client();
+    let mut logs = Vec::new();
+    for (page_start, page_end) in block_pages(from_block, to_block, page_size) {
+        // The logs are read directly since the event metadata of
+        // `query_with_meta` does not include the log index.
+        let buckets_updated = exchange
+            .buckets_updated_filter()
+            .from_block(page_start)
+            .to_block(page_end)
+            .filter;
+        let exchanged = exchange
+            .exchanged_filter()
+            .from_block(page_start)
+            .to_block(page_end)
+            .filter;
+        let (buckets_updated, exchanged) = try_join!(
+            client.get_logs(&buckets_updated),
+            client.get_logs(&exchanged)
+        )
+        .map_err(CelophaneError::from_node)?;
+
+        let mut page = Vec::with_capacity(buckets_updated.len() + exchanged.len());
+        for log in buckets_updated {
+            let (block_number, log_index, transaction_hash) = log_position(&log)?;
+            let event: BucketsUpdatedFilter = exchange
+                .decode_event("BucketsUpdated", log.topics, log.data)
+                .map_err(|err| CelophaneError::Decode(err.to_string()))?;
+            page.push(ExchangeLog {
+                event: ExchangeEvent::BucketsUpdated {
+                    gold_bucket: event.gold_bucket,
+                    stable_bucket: event.stable_bucket,
+                },
+                block_number,
+                log_index,
+                transaction_hash,
+            });
+        }
+        for log in exchanged {
+            let (block_number, log_index, transaction_hash) = log_position(&log)?;
+            let event: ExchangedFilter = exchange
+                .decode_event("Exchanged", log.topics, log.data)
+                .map_err(|err| CelophaneError::Decode(err.to_string()))?;
+            page.push(ExchangeLog {
+                event: ExchangeEvent::Exchanged {
+                    exchanger: event.exchanger,
+                    sell_amount: event.sell_amount,
+                    buy_amount: event.buy_amount,
+                    sold_gold: event.sold_gold,
+                },
+                block_number,
+                log_index,
+                transaction_hash,
+            });
+        }
+        page.sort_by_key(|log| (log.block_number, log.log_index));
+        logs.extend(page);
+    }
+    Ok(logs)
+}
+
+/// Block number, log index and transaction hash of a mined `log`.
+fn log_position(log: &Log) -> Result<(U64, U256, H256)> {
+    match (log.block_number, log.log_index, log.transaction_hash) {
+        (Some(block_number), Some(log_index), Some(transaction_hash)) => {
+            Ok((block_number, log_index, transaction_hash))
+        }
+        _ => Err(CelophaneError::Decode(
+            "Exchange log without a block, log index or transaction".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Buckets and spread of the reference quotes below. The quotes were
+    /// computed by replaying the FixidityLib arithmetic of
+    /// `Exchange.getBuyTokenAmount` and `getSellTokenAmount`, not read from a
+    /// deployed exchange, so they check the model against that arithmetic
+    /// rather than against the chain.
+    fn pool() -> MentoPool {
+        MentoPool::new(
+            U256::from_dec_str("7247139512345678901234567").unwrap(),
+            U256::from_dec_str("18120559000000000087654321").unwrap(),
+            // 0.5%
+            U256::exp10(21) * 5,
+        )
+    }
+
+    fn dec(s: &str) -> U256 {
+        U256::from_dec_str(s).unwrap()
+    }
+
+    #[test]
+    fn buy_token_amount_matches_reference_arithmetic() {
+        let pool = pool();
+        let one = U256::exp10(18);
+        let cases = [
+            (one, true, "2487871759451178433"),
+            (one, false, "397940450889688438"),
+            (one * 1_000_000, true, "2187533489089017972242003"),
+            (U256::from(123_456_789), false, "49128452"),
+        ];
+        for (sell_amount, sell_gold, expected) in cases.iter() {
+            assert_eq!(
+                pool.get_buy_token_amount(*sell_amount, *sell_gold),
+                Some(dec(expected)),
+                "selling {} (sell_gold: {})",
+                sell_amount,
+                sell_gold
+            );
+        }
+    }
+
+    #[test]
+    fn sell_token_amount_matches_reference_arithmetic() {
+        let pool = pool();
+        let one = U256::exp10(18);
+        let cases = [
+            (one, true, "401949945406751726"),
+            (one, false, "2512939010949383351"),
+            (
+                pool.stable_bucket - 1,
+                true,
+                "131982129763508646794089678455020189975942845104964",
+            ),
+        ];
+        for (buy_amount, sell_gold, expected) in cases.iter() {
+            assert_eq!(
+                pool.get_sell_token_amount(*buy_amount, *sell_gold),
+                Some(dec(expected)),
+                "buying {} (sell_gold: {})",
+                buy_amount,
+                sell_gold
+            );
+        }
+    }
+
+    #[test]
+    fn quotes_truncate_like_fixidity() {
+        let pool = pool();
+        // 2.49 stable tokens per unit, rounded down.
+        assert_eq!(pool.get_buy_token_amount(U256::one(), true), Some(2.into()));
+        // 1.19 units per 3 stable tokens.
+        assert_eq!(pool.get_buy_token_amount(3.into(), false), Some(1.into()));
+        // Less than a unit of CELO buys a unit of stable token.
+        assert_eq!(
+            pool.get_sell_token_amount(U256::one(), true),
+            Some(0.into())
+        );
+        assert_eq!(pool.get_sell_token_amount(7.into(), false), Some(17.into()));
+        assert_eq!(pool.get_buy_token_amount(0.into(), true), Some(0.into()));
+        assert_eq!(pool.get_sell_token_amount(0.into(), true), Some(0.into()));
+    }
+
+    #[test]
+    fn spread_digits_below_multiply_precision_match_reference_arithmetic() {
+        let pool = MentoPool {
+            spread: dec("3333333333333333333333"),
+            ..pool()
+        };
+        let one = U256::exp10(18);
+        assert_eq!(
+            pool.get_buy_token_amount(one, true),
+            Some(dec("2492039048257385058"))
+        );
+        assert_eq!(
+            pool.get_sell_token_amount(one, false),
+            Some(dec("2508736771800638563"))
+        );
+    }
+
+    #[test]
+    fn no_quote_where_contract_reverts() {
+        let pool = pool();
+        // Buying the whole bucket divides by zero, buying more underflows.
+        assert_eq!(pool.get_sell_token_amount(pool.stable_bucket, true), None);
+        assert_eq!(
+            pool.get_sell_token_amount(pool.gold_bucket + 1, false),
+            None
+        );
+
+        let one = U256::exp10(18);
+        let free = MentoPool {
+            spread: fixed1(),
+            ..pool
+        };
+        // A 100% spread leaves nothing to buy with, and nothing to sell for.
+        assert_eq!(free.get_buy_token_amount(one, true), Some(0.into()));
+        assert_eq!(free.get_sell_token_amount(one, true), None);
+
+        let negative = MentoPool {
+            spread: fixed1() + 1,
+            ..pool
+        };
+        assert_eq!(negative.get_buy_token_amount(one, true), None);
+        assert_eq!(negative.get_sell_token_amount(one, true), None);
+    }
+
+    #[test]
+    fn fixidity_multiply_truncates_fractional_product() {
+        let half = fixed1() / 2;
+        assert_eq!(fixidity_multiply(half, half), Some(fixed1() / 4));
+        // 1e-24 squared is dropped.
+        let tiny = U256::one();
+        assert_eq!(
+            fixidity_multiply(fixed1() + tiny, fixed1() + tiny),
+            Some(fixed1() + 2)
+        );
+        // Fractional parts are truncated to 12 digits before multiplying.
+        assert_eq!(
+            fixidity_multiply(mul_precision() - 1, mul_precision() - 1),
+            Some(0.into())
+        );
+        assert_eq!(
+            fixidity_multiply(mul_precision(), mul_precision()),
+            Some(1.into())
+        );
+        assert_eq!(
+            fixidity_multiply(
+                fixed1() * 3 + mul_precision() * 3 / 2,
+                fixed1() * 2 + mul_precision() * 5 / 2
+            ),
+            Some(dec("6000000000010500000000002"))
+        );
+        assert_eq!(fixidity_multiply(U256::MAX, fixed1() * 2), None);
+    }
+
+    #[test]
+    fn fixidity_ratio_of_buckets() {
+        assert_eq!(fixidity_ratio(5.into(), 2.into()), fixed1() * 5 / 2);
+        assert_eq!(
+            fixidity_ratio(1.into(), 3.into()),
+            dec("333333333333333333333333")
+        );
+        assert_eq!(fixidity_ratio(0.into(), 3.into()), U256::zero());
+        assert_eq!(fixidity_ratio(1.into(), 0.into()), U256::zero());
+    }
+}
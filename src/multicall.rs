@@ -0,0 +1,179 @@
+use crate::celo::Multicall;
+use crate::error::{CelophaneError, Result};
+use crate::transport::BatchClient;
+use ethers::contract::builders::ContractCall;
+use ethers::core::abi::Detokenize;
+use ethers::providers::Middleware;
+use ethers::types::{Address, BlockNumber, Bytes, NameOrAddress};
+use futures_util::stream::{self, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// Runs read-only contract calls in bulk.
+///
+/// With the address of a deployed [Multicall] contract, calls are aggregated
+/// into a single `eth_call`. Without one, or if the aggregated call fails,
+/// they are sent as a JSON-RPC batch of `eth_call`s if a batch client is
+/// given, and as individual `eth_call`s otherwise, at most
+/// [`DEFAULT_CONCURRENCY`] at once unless set otherwise. Either way, a single
+/// failing call does not fail the others.
+///
+/// [Multicall]: https://github.com/makerdao/multicall
+#[derive(Debug)]
+pub struct CallBatch<M> {
+    client: Arc<M>,
+    multicall: Option<Address>,
+    batch_client: Option<Arc<dyn BatchClient>>,
+    block: Option<BlockNumber>,
+    concurrency: usize,
+}
+
+/// Number of individual calls a [`CallBatch`] has in flight by default.
+pub const DEFAULT_CONCURRENCY: usize = 16;
+
+impl<M: Middleware> CallBatch<M> {
+    pub fn new(client: Arc<M>, multicall: Option<Address>) -> Self {
+        CallBatch {
+            client,
+            multicall,
+            batch_client: None,
+            block: None,
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+
+    /// Sends the calls through `batch_client` when they cannot be aggregated.
+    pub fn batch_client(mut self, batch_client: Option<Arc<dyn BatchClient>>) -> Self {
+        self.batch_client = batch_client;
+        self
+    }
+
+    /// Runs the calls against `block` rather than the latest block.
+    pub fn block(mut self, block: Option<BlockNumber>) -> Self {
+        self.block = block;
+        self
+    }
+
+    /// Sends at most `max` individual calls at once, when they cannot be
+    /// aggregated or batched.
+    pub fn concurrency(mut self, max: usize) -> Self {
+        self.concurrency = max.max(1);
+        self
+    }
+
+    /// Runs `calls`, returning their results in the same order.
+    pub async fn call<D: Detokenize>(&self, calls: Vec<ContractCall<M, D>>) -> Vec<Result<D>> {
+        if calls.is_empty() {
+            return Vec::new();
+        }
+        if let Some(address) = self.multicall {
+            if let Ok(results) = self.aggregate(address, &calls).await {
+                return results;
+            }
+        }
+        if let Some(batch_client) = &self.batch_client {
+            if let Ok(results) = self.send_batch(batch_client.as_ref(), &calls).await {
+                return results;
+            }
+        }
+
+        let calls: Vec<_> = calls
+            .into_iter()
+            .map(|call| match self.block {
+                Some(block) => call.block(block),
+                None => call,
+            })
+            .collect();
+        stream::iter(&calls)
+            .map(|call| call.call())
+            .buffered(self.concurrency)
+            .map(|result| result.map_err(CelophaneError::from))
+            .collect()
+            .await
+    }
+
+    async fn send_batch<D: Detokenize>(
+        &self,
+        batch_client: &dyn BatchClient,
+        calls: &[ContractCall<M, D>],
+    ) -> Result<Vec<Result<D>>> {
+        let requests = calls
+            .iter()
+            .map(|call| {
+                let block = self.block.or(call.block).unwrap_or(BlockNumber::Latest);
+                let params = Value::Array(vec![to_json(&call.tx)?, to_json(&block)?]);
+                Ok(("eth_call", params))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let results = batch_client
+            .request_batch(&requests)
+            .await
+            .map_err(CelophaneError::from_node)?;
+
+        Ok(calls
+            .iter()
+            .zip(results)
+            .map(|(call, result)| {
+                let data: Bytes =
+                    serde_json::from_value(result.map_err(CelophaneError::from_node)?)
+                        .map_err(|e| CelophaneError::Decode(e.to_string()))?;
+                decode_output(call, data.as_ref())
+            })
+            .collect())
+    }
+
+    async fn aggregate<D: Detokenize>(
+        &self,
+        address: Address,
+        calls: &[ContractCall<M, D>],
+    ) -> Result<Vec<Result<D>>> {
+        let targets = calls
+            .iter()
+            .map(|call| {
+                let target = match &call.tx.to {
+                    Some(NameOrAddress::Address(address)) => *address,
+                    _ => {
+                        return Err(CelophaneError::Decode(
+                            "call without a contract address".to_string(),
+                        ))
+                    }
+                };
+                let data = call.tx.data.as_ref().map(|data| data.0.to_vec());
+                Ok((target, data.unwrap_or_default()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let multicall = Multicall::new(address, self.client.clone());
+        let mut aggregate = multicall.aggregate(targets);
+        if let Some(block) = self.block {
+            aggregate = aggregate.block(block);
+        }
+        let (_, return_data) = aggregate.call().await?;
+        if return_data.len() != calls.len() {
+            return Err(CelophaneError::Decode(format!(
+                "multicall returned {} results for {} calls",
+                return_data.len(),
+                calls.len()
+            )));
+        }
+
+        Ok(calls
+            .iter()
+            .zip(return_data)
+            .map(|(call, data)| decode_output(call, &data))
+            .collect())
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<Value> {
+    serde_json::to_value(value).map_err(|e| CelophaneError::Decode(e.to_string()))
+}
+
+fn decode_output<M, D: Detokenize>(call: &ContractCall<M, D>, data: &[u8]) -> Result<D> {
+    let tokens = call
+        .function
+        .decode_output(data)
+        .map_err(|e| CelophaneError::Decode(e.to_string()))?;
+    D::from_tokens(tokens).map_err(|e| CelophaneError::Decode(e.to_string()))
+}
@@ -0,0 +1,92 @@
+use crate::celo::{self, ExchangePool};
+use crate::error::{CelophaneError, Result};
+use ethers::providers::Middleware;
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use url::Url;
+
+/// Multicall deployments on the public networks.
+const MAINNET_MULTICALL: &str = "75f59534dd892c1f8a7b172d639fa854d529ada3";
+const ALFAJORES_MULTICALL: &str = "387ce7960b5da5381de08ea4967b13a7c8cab3f6";
+
+/// Names of the networks known to this crate.
+pub const BUILTIN_NETWORKS: &[&str] = &["mainnet", "alfajores", "baklava", "local"];
+
+/// A Celo network: where to reach it and what to expect of it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Network {
+    /// Default endpoint of the network.
+    pub endpoint: Url,
+    /// Chain id the endpoint must report, `None` to accept any chain.
+    #[serde(default)]
+    pub chain_id: Option<u64>,
+    /// Address of the registry contract.
+    #[serde(default = "celo::registry_address")]
+    pub registry: Address,
+    /// Address of a Multicall contract to aggregate read calls through.
+    #[serde(default)]
+    pub multicall: Option<Address>,
+    /// Tokens outside of the registry, by symbol.
+    #[serde(default)]
+    pub tokens: BTreeMap<String, Address>,
+    /// Exchange pools besides the cUSD and cEUR ones.
+    #[serde(default)]
+    pub pools: Vec<ExchangePool>,
+}
+
+impl Network {
+    fn new(endpoint: &str, chain_id: Option<u64>) -> Self {
+        Network {
+            endpoint: endpoint.parse().unwrap(),
+            chain_id,
+            registry: celo::registry_address(),
+            multicall: None,
+            tokens: BTreeMap::new(),
+            pools: Vec::new(),
+        }
+    }
+
+    /// The network called `name` among [`BUILTIN_NETWORKS`], if any.
+    pub fn builtin(name: &str) -> Option<Self> {
+        let network = match name {
+            "mainnet" => Network {
+                multicall: Some(MAINNET_MULTICALL.parse().unwrap()),
+                ..Network::new("https://forno.celo.org", Some(42220))
+            },
+            "alfajores" => Network {
+                multicall: Some(ALFAJORES_MULTICALL.parse().unwrap()),
+                ..Network::new("https://alfajores-forno.celo-testnet.org", Some(44787))
+            },
+            "baklava" => Network::new("https://baklava-forno.celo-testnet.org", Some(62320)),
+            // Development chains use arbitrary chain ids.
+            "local" => Network::new("http://localhost:8545", None),
+            _ => return None,
+        };
+        Some(network)
+    }
+
+    /// The exchange pools of the network, the default ones first.
+    pub fn exchange_pools(&self) -> Vec<ExchangePool> {
+        let mut pools = ExchangePool::defaults();
+        pools.extend(self.pools.iter().cloned());
+        pools
+    }
+
+    /// Checks that `client` is connected to this network's chain.
+    pub async fn verify<M: Middleware>(&self, client: &M) -> Result<()> {
+        let expected = match self.chain_id {
+            Some(chain_id) => chain_id,
+            None => return Ok(()),
+        };
+        let actual = client
+            .get_chainid()
+            .await
+            .map_err(CelophaneError::from_node)?
+            .as_u64();
+        if actual != expected {
+            return Err(CelophaneError::WrongChain { expected, actual });
+        }
+        Ok(())
+    }
+}
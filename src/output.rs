@@ -0,0 +1,126 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::str::FromStr;
+
+/// Format command results are printed in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl OutputFormat {
+    pub const VARIANTS: &'static [&'static str] = &["text", "json", "csv"];
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(anyhow!("Unknown output format \"{}\"", s)),
+        }
+    }
+}
+
+/// A single command result, printed as text, as a JSON object or as a CSV row.
+pub trait Record: Serialize {
+    /// CSV column names, matching `fields`.
+    const HEADER: &'static [&'static str];
+
+    /// CSV column names of this record, for records whose columns depend on
+    /// the command's arguments. Records printed together share their columns.
+    fn header(&self) -> Vec<String> {
+        Self::HEADER
+            .iter()
+            .map(|column| column.to_string())
+            .collect()
+    }
+
+    /// CSV column values.
+    fn fields(&self) -> Vec<String>;
+
+    /// Human-readable rendering.
+    fn text(&self) -> String;
+}
+
+pub fn print_records<R: Record>(records: &[R], format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Text => {
+            for record in records {
+                println!("{}", record.text());
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(records)?),
+        OutputFormat::Csv => {
+            print_header(records);
+            for record in records {
+                print_row(record);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Prints records in batches as they arrive, e.g. while following the chain:
+/// the CSV header is only printed once and JSON is printed one object per line.
+pub struct RecordStream {
+    format: OutputFormat,
+    header_printed: bool,
+}
+
+impl RecordStream {
+    pub fn new(format: OutputFormat) -> Self {
+        RecordStream {
+            format,
+            header_printed: false,
+        }
+    }
+
+    pub fn print<R: Record>(&mut self, records: &[R]) -> Result<()> {
+        match self.format {
+            OutputFormat::Text => print_records(records, self.format)?,
+            OutputFormat::Json => {
+                for record in records {
+                    println!("{}", serde_json::to_string(record)?);
+                }
+            }
+            OutputFormat::Csv => {
+                if !self.header_printed {
+                    print_header(records);
+                    self.header_printed = true;
+                }
+                for record in records {
+                    print_row(record);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn print_header<R: Record>(records: &[R]) {
+    let header = match records.first() {
+        Some(record) => record.header(),
+        None => R::HEADER.iter().map(|column| column.to_string()).collect(),
+    };
+    let header: Vec<String> = header.iter().map(|c| csv_escape(c)).collect();
+    println!("{}", header.join(","));
+}
+
+fn print_row<R: Record>(record: &R) {
+    let fields: Vec<String> = record.fields().iter().map(|f| csv_escape(f)).collect();
+    println!("{}", fields.join(","));
+}
@@ -0,0 +1,354 @@
+use crate::celo;
+use crate::error::{CelophaneError, Result};
+use crate::multicall::CallBatch;
+use ethers::providers::Middleware;
+use ethers::types::{Address, BlockNumber, U64};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Registry identifiers of the Celo core contracts.
+pub const CORE_CONTRACTS: &[&str] = &[
+    "Accounts",
+    "Attestations",
+    "BlockchainParameters",
+    "DoubleSigningSlasher",
+    "DowntimeSlasher",
+    "Election",
+    "EpochRewards",
+    "Escrow",
+    "Exchange",
+    "ExchangeEUR",
+    "FeeCurrencyWhitelist",
+    "Freezer",
+    "GasPriceMinimum",
+    "GoldToken",
+    "Governance",
+    "GovernanceSlasher",
+    "LockedGold",
+    "Random",
+    "Reserve",
+    "SortedOracles",
+    "StableToken",
+    "StableTokenEUR",
+    "TransferWhitelist",
+    "Validators",
+];
+
+/// Seconds addresses resolved at the latest block are cached for by default.
+pub const DEFAULT_MAX_AGE_SECS: u64 = 3600;
+
+/// Number of past blocks whose addresses are cached at most.
+pub const MAX_PINNED_ENTRIES: usize = 64;
+
+/// Core contract addresses resolved on a chain at a block.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct CachedEntries {
+    chain_id: u64,
+    /// Address of the registry the addresses were resolved through.
+    #[serde(default = "celo::registry_address")]
+    registry: Address,
+    /// Block the addresses were resolved at, `None` for the latest block.
+    block: Option<U64>,
+    /// Unix time the addresses were resolved at.
+    #[serde(default)]
+    resolved_at: u64,
+    addresses: BTreeMap<String, Address>,
+}
+
+impl CachedEntries {
+    fn is_for(&self, chain_id: u64, registry: Address, block: Option<U64>) -> bool {
+        self.chain_id == chain_id && self.registry == registry && self.block == block
+    }
+}
+
+/// Memoized registry addresses of the core contracts, keyed by chain id,
+/// registry address and block.
+///
+/// All of [`CORE_CONTRACTS`] is resolved at once the first time a chain,
+/// registry and block are seen, so later lookups are free. Addresses resolved
+/// at the latest block are resolved again once older than the maximum age,
+/// since the registry may have been re-pointed since. The cache can be saved
+/// to and loaded from a JSON file to share it between processes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RegistryCache {
+    entries: Vec<CachedEntries>,
+    #[serde(skip, default = "default_max_age")]
+    max_age: Duration,
+}
+
+impl Default for RegistryCache {
+    fn default() -> Self {
+        RegistryCache {
+            entries: Vec::new(),
+            max_age: default_max_age(),
+        }
+    }
+}
+
+fn default_max_age() -> Duration {
+    Duration::from_secs(DEFAULT_MAX_AGE_SECS)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+impl RegistryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolves addresses cached at the latest block again once older than
+    /// `max_age`. Addresses pinned to a block never change.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Loads a cache from `path`, starting empty if the file does not exist or
+    /// cannot be parsed.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(cache) => Ok(cache),
+                Err(err) => {
+                    eprintln!(
+                        "Warning: ignoring registry cache {}: {}",
+                        path.display(),
+                        err
+                    );
+                    Ok(Self::new())
+                }
+            },
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::new()),
+            Err(err) => Err(CelophaneError::Cache(err.to_string())),
+        }
+    }
+
+    /// Saves the cache to `path`. The cache is written to a temporary file
+    /// next to it first, so that concurrent readers never see a partial file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let contents =
+            serde_json::to_string_pretty(self).map_err(|e| CelophaneError::Cache(e.to_string()))?;
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(format!(".{}.tmp", process::id()));
+        let temp = PathBuf::from(temp);
+        fs::write(&temp, contents)
+            .and_then(|_| fs::rename(&temp, path))
+            .map_err(|e| {
+                let _ = fs::remove_file(&temp);
+                CelophaneError::Cache(e.to_string())
+            })
+    }
+
+    /// The entries cached for `chain_id`, `registry` and `block`, unless they
+    /// expired.
+    fn entries(
+        &self,
+        chain_id: u64,
+        registry: Address,
+        block: Option<U64>,
+    ) -> Option<&CachedEntries> {
+        let now = now();
+        self.entries.iter().find(|entries| {
+            entries.is_for(chain_id, registry, block)
+                && (block.is_some()
+                    || now.saturating_sub(entries.resolved_at) <= self.max_age.as_secs())
+        })
+    }
+
+    /// Cached address of `name` in `registry` on `chain_id` at `block`, if any.
+    pub fn get(
+        &self,
+        chain_id: u64,
+        registry: Address,
+        block: Option<U64>,
+        name: &str,
+    ) -> Option<Address> {
+        self.entries(chain_id, registry, block)
+            .and_then(|entries| entries.addresses.get(name))
+            .copied()
+    }
+
+    /// Whether the core contracts of `registry` on `chain_id` are cached at
+    /// `block`.
+    pub fn contains(&self, chain_id: u64, registry: Address, block: Option<U64>) -> bool {
+        self.entries(chain_id, registry, block).is_some()
+    }
+
+    /// Caches `addresses`, replacing any expired entries of the same chain,
+    /// registry and block. Only the most recently resolved
+    /// [`MAX_PINNED_ENTRIES`] blocks are kept, so that reading many past
+    /// blocks does not grow the cache without bound.
+    fn insert(
+        &mut self,
+        chain_id: u64,
+        registry: Address,
+        block: Option<U64>,
+        addresses: BTreeMap<String, Address>,
+    ) {
+        self.entries
+            .retain(|entries| !entries.is_for(chain_id, registry, block));
+        self.entries.push(CachedEntries {
+            chain_id,
+            registry,
+            block,
+            resolved_at: now(),
+            addresses,
+        });
+
+        let pinned = self.entries.iter().filter(|e| e.block.is_some()).count();
+        if pinned > MAX_PINNED_ENTRIES {
+            // Entries are in order of resolution, oldest first.
+            let mut excess = pinned - MAX_PINNED_ENTRIES;
+            self.entries.retain(|entries| {
+                if excess > 0 && entries.block.is_some() {
+                    excess -= 1;
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+    }
+
+    /// Resolves every core contract on the client's chain, `chain_id`, unless
+    /// already cached for the same registry and block and not expired.
+    /// Contracts not deployed on the chain are left out. The lookups are made
+    /// against the registry at `registry` and sent together through `batch`.
+    pub async fn resolve_all<M: Middleware>(
+        &mut self,
+        client: Arc<M>,
+        batch: CallBatch<M>,
+        chain_id: u64,
+        registry: Address,
+        block: Option<U64>,
+    ) -> Result<()> {
+        if self.contains(chain_id, registry, block) {
+            return Ok(());
+        }
+
+        let registry_contract = celo::get_registry(client.clone(), registry);
+        let lookups = CORE_CONTRACTS
+            .iter()
+            .map(|name| registry_contract.get_address_for_string(name.to_string()))
+            .collect();
+        let results = batch
+            .block(block.map(BlockNumber::Number))
+            .call(lookups)
+            .await;
+        let mut addresses = BTreeMap::new();
+        for (name, result) in CORE_CONTRACTS.iter().zip(results) {
+            if let Ok(address) = celo::registry_result(name, result?) {
+                addresses.insert(name.to_string(), address);
+            }
+        }
+
+        self.insert(chain_id, registry, block, addresses);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses(address: u64) -> BTreeMap<String, Address> {
+        let mut addresses = BTreeMap::new();
+        addresses.insert("Exchange".to_string(), Address::from_low_u64_be(address));
+        addresses
+    }
+
+    #[test]
+    fn keys_entries_by_registry() {
+        let mut cache = RegistryCache::new();
+        let registry = celo::registry_address();
+        let other = Address::from_low_u64_be(0xce11);
+        cache.insert(1337, registry, None, addresses(1));
+        cache.insert(1337, other, None, addresses(2));
+
+        let get = |registry| cache.get(1337, registry, None, "Exchange");
+        assert_eq!(get(registry), Some(Address::from_low_u64_be(1)));
+        assert_eq!(get(other), Some(Address::from_low_u64_be(2)));
+        assert_eq!(cache.get(42220, registry, None, "Exchange"), None);
+    }
+
+    #[test]
+    fn expires_latest_block_entries_only() {
+        let registry = celo::registry_address();
+        let mut cache = RegistryCache::new();
+        cache.insert(42220, registry, None, addresses(1));
+        cache.insert(42220, registry, Some(U64::from(100)), addresses(2));
+        for entries in &mut cache.entries {
+            entries.resolved_at -= DEFAULT_MAX_AGE_SECS + 1;
+        }
+
+        assert!(!cache.contains(42220, registry, None));
+        assert!(cache.contains(42220, registry, Some(U64::from(100))));
+
+        cache.insert(42220, registry, None, addresses(3));
+        assert_eq!(cache.entries.len(), 2);
+        assert_eq!(
+            cache.get(42220, registry, None, "Exchange"),
+            Some(Address::from_low_u64_be(3))
+        );
+    }
+
+    #[test]
+    fn keeps_most_recent_pinned_entries() {
+        let registry = celo::registry_address();
+        let mut cache = RegistryCache::new();
+        cache.insert(42220, registry, None, addresses(1));
+        for block in 0..MAX_PINNED_ENTRIES as u64 + 10 {
+            cache.insert(42220, registry, Some(U64::from(block)), addresses(block));
+        }
+
+        assert_eq!(cache.entries.len(), MAX_PINNED_ENTRIES + 1);
+        assert!(cache.contains(42220, registry, None));
+        assert!(!cache.contains(42220, registry, Some(U64::from(9))));
+        assert!(cache.contains(42220, registry, Some(U64::from(10))));
+    }
+
+    #[test]
+    fn loads_entries_without_registry_or_resolution_time() {
+        let cache: RegistryCache = serde_json::from_str(
+            r#"{"entries":[{"chain_id":42220,"block":null,"addresses":{}},
+                {"chain_id":42220,"block":"0x64","addresses":{}}]}"#,
+        )
+        .unwrap();
+        let registry = celo::registry_address();
+        // Entries of unknown age are resolved again.
+        assert!(!cache.contains(42220, registry, None));
+        assert!(cache.contains(42220, registry, Some(U64::from(100))));
+    }
+
+    #[test]
+    fn saves_and_loads_entries() {
+        let path = std::env::temp_dir().join(format!("celophane-cache-{}.json", process::id()));
+        let registry = celo::registry_address();
+        let mut cache = RegistryCache::new();
+        cache.insert(42220, registry, Some(U64::from(100)), addresses(1));
+        cache.save(&path).unwrap();
+
+        let loaded = RegistryCache::load(&path).unwrap();
+        assert_eq!(
+            loaded.get(42220, registry, Some(U64::from(100)), "Exchange"),
+            Some(Address::from_low_u64_be(1))
+        );
+
+        fs::write(&path, "{\"entries\":").unwrap();
+        let loaded = RegistryCache::load(&path).unwrap();
+        assert!(loaded.entries.is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
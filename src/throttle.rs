@@ -0,0 +1,167 @@
+use crate::transport::{BatchClient, JsonRpcError};
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, ProviderError};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::convert::TryFrom;
+use std::fmt::Debug;
+use std::future::Future;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::time::{self, Instant};
+
+#[derive(Debug, Error)]
+pub enum ThrottleError<E: std::error::Error + 'static> {
+    #[error("request timed out after {0:?}")]
+    Timeout(Duration),
+
+    /// The request failed in the inner client.
+    #[error(transparent)]
+    Client(E),
+}
+
+impl<E> From<ThrottleError<E>> for ProviderError
+where
+    E: std::error::Error + Into<ProviderError> + Send + Sync + 'static,
+{
+    fn from(src: ThrottleError<E>) -> Self {
+        match src {
+            ThrottleError::Client(err) => err.into(),
+            err => ProviderError::JsonRpcClientError(Box::new(err)),
+        }
+    }
+}
+
+/// Bounds the requests sent through a JSON-RPC client, e.g. a
+/// [`FailoverClient`](crate::FailoverClient): how long each may take, how many
+/// may be in flight at once and how many may start per second.
+///
+/// Every node request goes through the provider's client, so the limits apply
+/// to contract calls as well as to plain RPC calls. No limit is set by
+/// default. Clones share their limits, and count each request of a batch
+/// towards the rate limit.
+#[derive(Debug)]
+pub struct ThrottledClient<C> {
+    inner: Arc<C>,
+    timeout: Option<Duration>,
+    concurrency: Option<Arc<Semaphore>>,
+    /// Delay between the start of two requests.
+    interval: Option<Duration>,
+    /// Earliest time the next request may start.
+    next_start: Arc<Mutex<Instant>>,
+}
+
+impl<C> Clone for ThrottledClient<C> {
+    fn clone(&self) -> Self {
+        ThrottledClient {
+            inner: self.inner.clone(),
+            timeout: self.timeout,
+            concurrency: self.concurrency.clone(),
+            interval: self.interval,
+            next_start: self.next_start.clone(),
+        }
+    }
+}
+
+impl<C> ThrottledClient<C> {
+    pub fn new(inner: C) -> Self {
+        ThrottledClient {
+            inner: Arc::new(inner),
+            timeout: None,
+            concurrency: None,
+            interval: None,
+            next_start: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Fails requests that take longer than `timeout`, retries included.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Queues requests while `max` of them are in flight.
+    pub fn with_max_concurrency(mut self, max: Option<NonZeroUsize>) -> Self {
+        self.concurrency = max.map(|max| Arc::new(Semaphore::new(max.get())));
+        self
+    }
+
+    /// Spaces requests out so that at most `rate` of them start per second.
+    pub fn with_requests_per_second(mut self, rate: Option<NonZeroU32>) -> Self {
+        self.interval = rate.map(|rate| Duration::from_secs(1) / rate.get());
+        self
+    }
+
+    /// Waits until the rate limit lets `requests` more requests start.
+    async fn wait_turn(&self, requests: u32) {
+        if let Some(interval) = self.interval {
+            let start = {
+                let mut next_start = self.next_start.lock().await;
+                let start = (*next_start).max(Instant::now());
+                *next_start = start + interval * requests;
+                start
+            };
+            time::sleep_until(start).await;
+        }
+    }
+
+    /// Runs `request`, standing for `requests` requests, within the limits.
+    async fn throttle<R, E, F>(&self, requests: u32, request: F) -> Result<R, ThrottleError<E>>
+    where
+        E: std::error::Error + 'static,
+        F: Future<Output = Result<R, E>>,
+    {
+        let _permit = match &self.concurrency {
+            Some(semaphore) => Some(
+                semaphore
+                    .acquire()
+                    .await
+                    .expect("the semaphore is never closed"),
+            ),
+            None => None,
+        };
+        self.wait_turn(requests).await;
+
+        match self.timeout {
+            Some(timeout) => match time::timeout(timeout, request).await {
+                Ok(result) => result.map_err(ThrottleError::Client),
+                Err(_) => Err(ThrottleError::Timeout(timeout)),
+            },
+            None => request.await.map_err(ThrottleError::Client),
+        }
+    }
+}
+
+#[async_trait]
+impl<C> JsonRpcClient for ThrottledClient<C>
+where
+    C: JsonRpcClient,
+    C::Error: Send + Sync + 'static,
+{
+    type Error = ThrottleError<C::Error>;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: Serialize + DeserializeOwned,
+    {
+        self.throttle(1, self.inner.request(method, params)).await
+    }
+}
+
+#[async_trait]
+impl<C: BatchClient> BatchClient for ThrottledClient<C> {
+    async fn request_batch(
+        &self,
+        requests: &[(&str, Value)],
+    ) -> Result<Vec<Result<Value, JsonRpcError>>, ProviderError> {
+        let count = u32::try_from(requests.len()).unwrap_or(u32::MAX);
+        let results = self
+            .throttle(count, self.inner.request_batch(requests))
+            .await;
+        Ok(results?)
+    }
+}
@@ -0,0 +1,324 @@
+use crate::error::{CelophaneError, Result};
+#[cfg(unix)]
+use crate::ipc::{Ipc, IpcError};
+use async_trait::async_trait;
+use ethers::providers::{Http, JsonRpcClient, ProviderError, Ws};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use thiserror::Error;
+use url::Url;
+
+/// A JSON-RPC error returned by the node.
+#[derive(Clone, Debug, Deserialize, Error)]
+#[error("(code: {code}, message: {message}, data: {data:?})")]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Response {
+    pub id: Option<u64>,
+    #[serde(default)]
+    pub result: Value,
+    pub error: Option<JsonRpcError>,
+}
+
+/// A client able to send several JSON-RPC requests at once.
+#[async_trait]
+pub trait BatchClient: Debug + Send + Sync {
+    /// Sends `requests`, as method and parameters, in a single JSON-RPC batch.
+    /// Returns the outcome of each request in the same order, or an error if
+    /// the batch as a whole failed, e.g. because the transport does not
+    /// support batches.
+    async fn request_batch(
+        &self,
+        requests: &[(&str, Value)],
+    ) -> std::result::Result<Vec<std::result::Result<Value, JsonRpcError>>, ProviderError>;
+}
+
+/// A JSON-RPC connection to a node over any of the supported transports.
+///
+/// The ethers HTTP client sends requests one at a time, so batches go through
+/// a separate client to the same URL.
+#[derive(Debug)]
+pub enum Transport {
+    Http(Http, HttpBatch),
+    Ws(Ws),
+    #[cfg(unix)]
+    Ipc(Ipc),
+}
+
+/// Sends JSON-RPC batches over HTTP.
+#[derive(Debug)]
+pub struct HttpBatch {
+    url: Url,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Error)]
+pub enum TransportError {
+    #[error(transparent)]
+    Http(<Http as JsonRpcClient>::Error),
+
+    #[error(transparent)]
+    Ws(Box<<Ws as JsonRpcClient>::Error>),
+
+    #[cfg(unix)]
+    #[error(transparent)]
+    Ipc(IpcError),
+
+    /// The transport has no way to send JSON-RPC batches.
+    #[error("JSON-RPC batches are not supported over this transport")]
+    BatchUnsupported,
+
+    /// The node turned down a batch as a whole.
+    #[error("batch rejected: {0}")]
+    BatchRejected(JsonRpcError),
+
+    /// The node did not answer a batch with one response per request.
+    #[error("invalid batch response: {0}")]
+    InvalidBatchResponse(String),
+}
+
+type HttpError = <Http as JsonRpcClient>::Error;
+type WsError = <Ws as JsonRpcClient>::Error;
+
+impl TransportError {
+    /// Code of the JSON-RPC error the node answered with, if the request
+    /// reached the node.
+    pub fn json_rpc_code(&self) -> Option<i64> {
+        match self {
+            TransportError::Http(HttpError::JsonRpcError(err)) => Some(err.code),
+            TransportError::Ws(err) => match err.as_ref() {
+                WsError::JsonRpcError(err) => Some(err.code),
+                _ => None,
+            },
+            #[cfg(unix)]
+            TransportError::Ipc(IpcError::JsonRpcError(err)) => Some(err.code),
+            TransportError::BatchRejected(err) => Some(err.code),
+            _ => None,
+        }
+    }
+}
+
+impl From<TransportError> for ProviderError {
+    fn from(src: TransportError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(src))
+    }
+}
+
+impl Transport {
+    /// Connects to `url`, picking the transport from its scheme: "http",
+    /// "https", "ws", "wss" or "ipc" (e.g. ipc:///path/to/geth.ipc).
+    pub async fn connect(url: &Url) -> Result<Self> {
+        match url.scheme() {
+            "https" | "http" => Ok(Transport::Http(
+                Http::new(url.clone()),
+                HttpBatch {
+                    url: url.clone(),
+                    client: reqwest::Client::new(),
+                },
+            )),
+            "wss" | "ws" => Ws::connect(url.as_str())
+                .await
+                .map(Transport::Ws)
+                .map_err(CelophaneError::from_node),
+            #[cfg(unix)]
+            "ipc" => Ipc::connect(ipc_path(url)?)
+                .await
+                .map(Transport::Ipc)
+                .map_err(CelophaneError::from_node),
+            scheme => Err(CelophaneError::Transport(format!(
+                "unknown URL scheme \"{}\"",
+                scheme
+            ))),
+        }
+    }
+
+    /// Sends `requests`, as method and parameters, in a single JSON-RPC batch.
+    /// Batches are not supported over WebSocket.
+    pub async fn request_batch(
+        &self,
+        requests: &[(&str, Value)],
+    ) -> std::result::Result<Vec<std::result::Result<Value, JsonRpcError>>, TransportError> {
+        let payload: Vec<Value> = requests
+            .iter()
+            .enumerate()
+            .map(|(id, (method, params))| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "method": method,
+                    "params": params,
+                })
+            })
+            .collect();
+        let answer = match self {
+            Transport::Http(_, batch) => {
+                batch.send(&payload).await.map_err(TransportError::Http)?
+            }
+            Transport::Ws(_) => return Err(TransportError::BatchUnsupported),
+            #[cfg(unix)]
+            Transport::Ipc(ipc) => ipc
+                .request_batch(&payload)
+                .await
+                .map_err(TransportError::Ipc)?,
+        };
+        batch_results(answer, requests.len())
+    }
+}
+
+impl HttpBatch {
+    async fn send(&self, payload: &[Value]) -> std::result::Result<Value, HttpError> {
+        let text = self
+            .client
+            .post(self.url.clone())
+            .json(payload)
+            .send()
+            .await?
+            .text()
+            .await?;
+        serde_json::from_str(&text).map_err(|err| HttpError::SerdeJson { err, text })
+    }
+}
+
+/// Matches the node's answer to a batch of `count` requests, numbered from
+/// zero, to the requests.
+fn batch_results(
+    answer: Value,
+    count: usize,
+) -> std::result::Result<Vec<std::result::Result<Value, JsonRpcError>>, TransportError> {
+    let invalid = |err: String| TransportError::InvalidBatchResponse(err);
+    let responses = match answer {
+        Value::Array(responses) => responses,
+        // A single error is the node turning down the whole batch.
+        answer => {
+            let response: Response =
+                serde_json::from_value(answer).map_err(|err| invalid(err.to_string()))?;
+            return Err(match response.error {
+                Some(err) => TransportError::BatchRejected(err),
+                None => invalid("expected an array of responses".to_string()),
+            });
+        }
+    };
+
+    let mut results = vec![None; count];
+    for response in responses {
+        let response: Response =
+            serde_json::from_value(response).map_err(|err| invalid(err.to_string()))?;
+        let slot = response
+            .id
+            .and_then(|id| results.get_mut(id as usize))
+            .ok_or_else(|| invalid(format!("unexpected response id {:?}", response.id)))?;
+        *slot = Some(match response.error {
+            Some(err) => Err(err),
+            None => Ok(response.result),
+        });
+    }
+    results
+        .into_iter()
+        .enumerate()
+        .map(|(id, result)| result.ok_or_else(|| invalid(format!("no response to request {}", id))))
+        .collect()
+}
+
+#[async_trait]
+impl JsonRpcClient for Transport {
+    type Error = TransportError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> std::result::Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: Serialize + DeserializeOwned,
+    {
+        match self {
+            Transport::Http(http, _) => http
+                .request(method, params)
+                .await
+                .map_err(TransportError::Http),
+            Transport::Ws(ws) => ws
+                .request(method, params)
+                .await
+                .map_err(|err| TransportError::Ws(Box::new(err))),
+            #[cfg(unix)]
+            Transport::Ipc(ipc) => ipc
+                .request(method, params)
+                .await
+                .map_err(TransportError::Ipc),
+        }
+    }
+}
+
+/// Parses an endpoint: a URL, or the filesystem path of an IPC socket.
+pub fn endpoint_url(endpoint: &str) -> Result<Url> {
+    let invalid = || CelophaneError::Transport(format!("invalid endpoint \"{}\"", endpoint));
+    match Url::parse(endpoint) {
+        Ok(url) => Ok(url),
+        Err(url::ParseError::RelativeUrlWithoutBase) => {
+            let cwd = env::current_dir().map_err(|e| CelophaneError::Transport(e.to_string()))?;
+            // File URLs encode paths the same way, e.g. spaces.
+            let file_url = Url::from_file_path(cwd.join(endpoint)).map_err(|_| invalid())?;
+            Url::parse(&format!("ipc://{}", file_url.path())).map_err(|_| invalid())
+        }
+        Err(_) => Err(invalid()),
+    }
+}
+
+/// The socket path of an `ipc://` URL.
+fn ipc_path(url: &Url) -> Result<PathBuf> {
+    let file_url = Url::parse(&format!("file://{}", url.path()));
+    match (url.host(), file_url.map(|file_url| file_url.to_file_path())) {
+        (None, Ok(Ok(path))) => Ok(path),
+        _ => Err(CelophaneError::Transport(format!(
+            "invalid IPC endpoint \"{}\", expected ipc:///path/to/socket",
+            url
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_batch_responses_by_id() {
+        let answer = json!([
+            {"jsonrpc": "2.0", "id": 1, "error": {"code": 3, "message": "execution reverted"}},
+            {"jsonrpc": "2.0", "id": 0, "result": "0x01"},
+        ]);
+        let results = batch_results(answer, 2).unwrap();
+        assert_eq!(results[0].as_ref().unwrap(), &json!("0x01"));
+        assert_eq!(results[1].as_ref().unwrap_err().code, 3);
+    }
+
+    #[test]
+    fn reports_rejected_batches() {
+        let answer = json!({
+            "jsonrpc": "2.0",
+            "id": null,
+            "error": {"code": -32005, "message": "rate limit exceeded"},
+        });
+        let err = batch_results(answer, 2).unwrap_err();
+        assert!(matches!(err, TransportError::BatchRejected(_)));
+        assert_eq!(err.json_rpc_code(), Some(-32005));
+    }
+
+    #[test]
+    fn rejects_incomplete_batch_responses() {
+        let answer = json!([{"jsonrpc": "2.0", "id": 0, "result": "0x01"}]);
+        assert!(matches!(
+            batch_results(answer, 2),
+            Err(TransportError::InvalidBatchResponse(_))
+        ));
+        let answer = json!([{"jsonrpc": "2.0", "id": 2, "result": "0x01"}]);
+        assert!(matches!(
+            batch_results(answer, 2),
+            Err(TransportError::InvalidBatchResponse(_))
+        ));
+    }
+}